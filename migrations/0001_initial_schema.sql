@@ -0,0 +1,81 @@
+CREATE TABLE users (
+   id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+   username TEXT UNIQUE NOT NULL,
+   password TEXT NOT NULL,
+   role TEXT NOT NULL DEFAULT 'user' CHECK (role IN ('user', 'admin')), -- Admins access every sensor
+   token_version INT NOT NULL DEFAULT 0,
+   created_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Long-lived opaque tokens renewing access JWTs, only their digest is stored
+CREATE TABLE refresh_tokens (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT UNIQUE NOT NULL, -- BLAKE3 hex digest
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE sensors (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    name VARCHAR(255),
+    location VARCHAR(255),
+    sensor_type VARCHAR(64), -- Device class, e.g. hardware model, for fleet-wide policies
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE, -- Disabled sensors can't ingest readings
+    coalesce_readings BOOLEAN NOT NULL DEFAULT FALSE,
+    past_reading_policy TEXT CHECK (past_reading_policy IN ('accept', 'reject', 'flag')), -- NULL uses PAST_READING_POLICY
+    strict_ordering BOOLEAN NOT NULL DEFAULT FALSE, -- Reject readings not newer than the latest one
+    min_interval_secs INT CHECK (min_interval_secs > 0), -- Optional minimum sampling interval
+    -- Optional validation bounds, NULL falls back to the global defaults
+    co2_min REAL,
+    co2_max REAL,
+    temperature_min REAL,
+    temperature_max REAL,
+    forward_url TEXT, -- Optional endpoint receiving every accepted reading
+    alert_threshold REAL, -- CO2 level (ppm) above which readings raise an alert, NULL disables alerts
+    api_key_hash TEXT, -- Argon2 hash of the ingest API key, NULL refuses ingest
+    signing_secret TEXT, -- HMAC-SHA256 key of signed readings, kept in clear to verify them
+    require_signature BOOLEAN NOT NULL DEFAULT FALSE, -- Refuse readings without an X-Signature
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE TABLE readings (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    sensor_id INTEGER NOT NULL REFERENCES sensors(id),
+    timestamp TIMESTAMPTZ NOT NULL,
+    co2_level REAL NOT NULL,
+    temperature REAL NOT NULL,
+    -- Optional weather-station fields
+    pressure REAL, -- hPa
+    wind_speed REAL, -- m/s
+    wind_direction REAL, -- Degrees from north
+    -- Optional air-quality fields
+    pm25 REAL, -- µg/m³
+    pm10 REAL, -- µg/m³
+    humidity REAL, -- Relative humidity, %
+    tx_signature TEXT NOT NULL,
+    seq BIGINT CHECK (seq >= 0), -- Firmware-reported sequence number, if any
+    backfilled BOOLEAN NOT NULL DEFAULT FALSE, -- Past-dated reading flagged by its sensor's policy
+    last_seen TIMESTAMPTZ -- Latest identical reading folded into this one (coalescing)
+);
+
+CREATE TABLE alerts (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    sensor_id INTEGER NOT NULL REFERENCES sensors(id) ON DELETE CASCADE,
+    reading_id INTEGER NOT NULL REFERENCES readings(id) ON DELETE CASCADE,
+    co2_level REAL NOT NULL,
+    threshold REAL NOT NULL, -- The sensor's alert_threshold when the alert was raised
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Revoke UPDATE entirely - readings are now truly immutable
+REVOKE UPDATE ON readings FROM PUBLIC;
+
+-- Indexes to enhance performance
+-- A sensor reports a single reading per timestamp, so retried readings can't be stored twice
+CREATE UNIQUE INDEX idx_sensor_readings_timestamp ON readings(sensor_id, timestamp);
+-- Idempotency key for devices reporting sequence numbers (readings without one never conflict)
+CREATE UNIQUE INDEX idx_sensor_readings_seq ON readings(sensor_id, seq);
+CREATE INDEX idx_sensor_user_id ON sensors(user_id);
+CREATE INDEX idx_alerts_sensor_created ON alerts(sensor_id, created_at);
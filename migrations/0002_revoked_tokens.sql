@@ -0,0 +1,8 @@
+-- Access JWTs revoked before their expiry, by jti. Entries past expires_at are useless,
+-- since the token itself no longer validates, and are purged on the next revocation.
+CREATE TABLE revoked_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL -- Original expiry of the token
+);
+
+CREATE INDEX idx_revoked_tokens_expires_at ON revoked_tokens (expires_at);
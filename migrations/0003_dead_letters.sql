@@ -0,0 +1,9 @@
+-- Readings whose proof submission failed, kept until a retry anchors them. The reading
+-- itself stays stored without a proof meanwhile.
+CREATE TABLE dead_letters (
+    id SERIAL PRIMARY KEY,
+    reading_id INTEGER NOT NULL UNIQUE REFERENCES readings(id) ON DELETE CASCADE,
+    reason TEXT NOT NULL, -- Error of the latest failed submission
+    attempts INTEGER NOT NULL DEFAULT 1,
+    failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+-- Pending proofs given up on by an admin, e.g. once the wallet is drained for good: the
+-- reading stays stored without a proof and is no longer listed nor retried
+ALTER TABLE readings ADD COLUMN proof_cancelled_at TIMESTAMPTZ;
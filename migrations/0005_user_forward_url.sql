@@ -0,0 +1,2 @@
+-- Account-wide forwarding target, used by the user's sensors without a forward_url of their own
+ALTER TABLE users ADD COLUMN forward_url TEXT;
@@ -0,0 +1,4 @@
+-- Opt-in humidity compensation of NDIR CO2 readings, the corrected value stored next to the
+-- raw co2_level, which stays the one anchored on-chain
+ALTER TABLE sensors ADD COLUMN humidity_compensation BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE readings ADD COLUMN co2_compensated REAL;
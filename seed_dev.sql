@@ -0,0 +1,10 @@
+-- Development data, loaded once migrations created the schema:
+-- psql -f seed_dev.sql pollution_tracker
+
+-- Insert test user
+INSERT INTO users (username, password, role)
+VALUES ('lluis', '$argon2id$v=19$m=19456,t=2,p=1$oz7KqblJDGMiSiaMPo9QyQ$QnuRj/P2YNuCUPdi2LrxBk/Kf85UwZj5qXxr6+zoJK8', 'user');
+
+-- Insert test sensors, with API keys dev-sensor-key-N and signing secrets dev-sensor-secret-N
+INSERT INTO sensors (name, location, user_id, api_key_hash, signing_secret) VALUES ('Fumera 1', 'Petroquimica Repsol TGN', '1', '$argon2id$v=19$m=19456,t=2,p=1$XyTPyA9mWdHYoxax9vKpBQ$jDWA4vXlFoYVFJVM2vLjvpB25ShHGkHvPpRcOwEh5oE', 'dev-sensor-secret-1');
+INSERT INTO sensors (name, location, user_id, api_key_hash, signing_secret) VALUES ('Aircraft cabin', 'LH1811', '1', '$argon2id$v=19$m=19456,t=2,p=1$V5j1Uuduq13ZeaphxedsxQ$fmvUqAUfHYyiLsfAGz+Ch4CETHtgoRBv3gtP7HQk56A', 'dev-sensor-secret-2');
@@ -1,38 +1,319 @@
 use crate::auth::Claims;
-use crate::db::{SensorReading, UserForm};
-use crate::http::{HttpResponse, LoginResponse, TimeRangeQuery};
+use crate::config::Config;
+use crate::crypto::BodyMac;
+use crate::db::{
+    PasswordConfirmation, PasswordForm, RefreshForm, SensorForm, SensorPatch, SensorReading,
+    UserForm, UserForwarding,
+};
+use crate::error::ApiError;
+use crate::forward::{self, Forwarder};
+use crate::http::{
+    ActivityQuery, AggregateQuery, BatchMode, BatchQuery, CancelProofsRequest, CountQuery,
+    DeleteSensorQuery, GapsQuery, HttpResponse, IngestQuery, IngestReceipt, LoginResponse,
+    MultiReadingsRequest, NewSensor, PendingProofsQuery, ProofStatusQuery, ReadingCount,
+    RecentQuery, SensorsQuery, TimeRangeQuery, UnitQuery,
+};
+use crate::proofs::ProofJob;
+use crate::ratelimit::RateLimiter;
 use crate::solana::SolanaClient;
-use crate::{auth, db};
+use crate::{aqi, auth, crypto, db, export, health, lamports, solana, telemetry};
+use argon2::Argon2;
+use axum::body::{Body, Bytes};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::{Path, Query, State};
-use axum::response::IntoResponse;
-use axum::routing::get;
+use axum::http::{HeaderMap, HeaderName, header};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, patch, post};
 use axum::{Extension, Json, Router, middleware};
-use serde_json::json;
+use chrono::{DateTime, Utc};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Serialize;
+use serde_json::{Value, json};
 use sqlx::{Error, PgPool};
-use std::sync::Arc;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::mpsc::PermitIterator;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Readings a JSON batch may hold, well below PostgreSQL's bind parameter limit
+const MAX_BATCH_READINGS: usize = 1000;
+
+/// Readings an atomic batch may hold, since they are all validated before any is anchored
+const MAX_ATOMIC_READINGS: usize = 10_000;
+
+/// Rejected lines detailed in a batch summary, the rest are only counted
+const MAX_REPORTED_REJECTIONS: usize = 1000;
+
+/// Header carrying a sensor's ingest API key
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header carrying the hex-encoded HMAC-SHA256 of a reading's raw body
+const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Estimated readings below which `exact_count=false` still counts exactly, being cheap
+const EXACT_COUNT_THRESHOLD: i64 = 10_000;
+
+/// Bytes buffered between a Parquet export and the response body
+const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of ingested readings buffered for slow live subscribers
+const READINGS_CHANNEL_CAPACITY: usize = 256;
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
     pub client: Arc<SolanaClient>,
+    pub config: Arc<Config>,
+    pub readings: broadcast::Sender<SensorReading>,
+    pub forwarder: Arc<Forwarder>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub metrics: PrometheusHandle,
+    pub proofs: mpsc::Sender<ProofJob>,
+    pub argon2: Argon2<'static>, // Hashes passwords and sensor API keys
 }
 
 impl AppState {
-    pub fn new(pool: PgPool, client: SolanaClient) -> Self {
-        Self {
+    pub fn new(
+        pool: PgPool,
+        client: SolanaClient,
+        config: Config,
+        metrics: PrometheusHandle,
+        proofs: mpsc::Sender<ProofJob>,
+    ) -> anyhow::Result<Self> {
+        let (readings, _) = broadcast::channel(READINGS_CHANNEL_CAPACITY);
+        let forwarder = Forwarder::new(&config.forward)?;
+        let rate_limiter = RateLimiter::new(&config.rate_limit);
+        let argon2 = Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            config.argon2.clone(),
+        );
+        Ok(Self {
             pool,
             client: Arc::new(client),
-        }
+            config: Arc::new(config),
+            readings,
+            forwarder: Arc::new(forwarder),
+            rate_limiter: Arc::new(rate_limiter),
+            metrics,
+            proofs,
+            argon2,
+        })
+    }
+
+    /// Hard ceiling on rows returned by read endpoints
+    fn max_rows(&self) -> usize {
+        self.config.max_response_rows
+    }
+
+    /// The sensor's type, if operators blocked ingest for it
+    fn blocked_type<'a>(&self, settings: &'a db::IngestSettings) -> Option<&'a str> {
+        settings
+            .sensor_type
+            .as_deref()
+            .filter(|t| self.config.blocked_sensor_types.contains(*t))
+    }
+
+    /// Row limit for read queries: one past the ceiling, so overflowing results can be detected
+    fn row_limit(&self) -> i64 {
+        self.max_rows() as i64 + 1
     }
 }
 
 // Define routes that require authentication
-pub fn protected_routes() -> Router<AppState> {
-    Router::new()
+pub fn protected_routes(state: &AppState) -> Router<AppState> {
+    let mut router = Router::new();
+    if state.config.features.sse {
+        router = router.route("/sensors/{sensor_id}/events", get(stream_readings));
+    }
+
+    router
         .route("/sensors/{sensor_id}/readings", get(fetch_reading))
-        .route("/sensors", get(fetch_sensors))
+        .route("/sensors/{sensor_id}/readings/count", get(count_readings))
+        .route(
+            "/sensors/{sensor_id}/readings/recent",
+            get(fetch_recent_readings),
+        )
+        .route(
+            "/sensors/{sensor_id}/readings/aggregate",
+            get(fetch_reading_aggregates),
+        )
+        .route(
+            "/sensors/{sensor_id}/readings/{reading_id}",
+            get(fetch_sensor_reading),
+        )
+        .route(
+            "/sensors/{sensor_id}/readings/{reading_id}/verify",
+            get(verify_sensor_reading),
+        )
+        .route(
+            "/sensors/{sensor_id}/readings.parquet",
+            get(export_readings_parquet),
+        )
+        .route(
+            "/sensors/{sensor_id}/readings.csv",
+            get(export_readings_csv),
+        )
+        .route("/sensors/{sensor_id}/gaps", get(fetch_gaps))
+        .route("/sensors/{sensor_id}/alerts", get(fetch_alerts))
+        .route("/sensors/{sensor_id}/health", get(fetch_sensor_health))
+        .route("/sensors/{sensor_id}/aqi", get(fetch_sensor_aqi))
+        .route(
+            "/sensors/{sensor_id}/missing-sequences",
+            get(fetch_missing_sequences),
+        )
+        .route("/sensors", get(fetch_sensors).post(create_sensor))
+        .route("/sensors/readings", post(fetch_readings_multi))
+        .route("/sensors/latest", get(fetch_latest_readings))
+        .route("/sensors/{sensor_id}/latest", get(fetch_latest_reading))
+        .route("/sensors/activity", get(fetch_sensors_activity))
+        .route(
+            "/sensors/{sensor_id}",
+            get(fetch_sensor).patch(update_sensor).delete(delete_sensor),
+        )
         .route("/verify/{reading_id}", get(verify_reading))
-        .layer(middleware::from_fn(auth::verify_jwt))
+        .route("/auth/logout-all", post(logout_all))
+        .route("/users/password", post(change_password))
+        .route("/users/me", delete(delete_account))
+        .route("/users/me/forwarding", patch(update_forwarding))
+        .route("/admin/sensors", get(fetch_all_sensors))
+        .route("/admin/dead-letters", get(fetch_dead_letters))
+        .route("/admin/dead-letters/{id}/retry", post(retry_dead_letter))
+        .route("/admin/pending-proofs", get(fetch_pending_proofs))
+        .route("/admin/pending-proofs/cancel", post(cancel_pending_proofs))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::verify_jwt,
+        ))
+}
+
+/// Access control: check if user owns the sensor, admins may access any sensor.
+/// Missing sensors are told apart (404) from sensors owned by someone else (403).
+async fn authorize_sensor(
+    state: &AppState,
+    claims: &Claims,
+    sensor_id: i32,
+) -> Result<(), ApiError> {
+    let username = claims.sub.as_str();
+    let access = match claims.is_admin() {
+        true => db::sensor_exists(&state.pool, sensor_id)
+            .await
+            .map(|exists| exists.then_some(true)),
+        false => db::sensor_access(&state.pool, username.to_string(), sensor_id).await,
+    };
+    match access {
+        Ok(Some(true)) => Ok(()),
+        Ok(Some(false)) => Err(ApiError::NotOwner),
+        Ok(None) => Err(ApiError::SensorNotFound),
+        Err(e) => {
+            tracing::error!(
+                sensor_id,
+                username,
+                "Database error checking sensor ownership: {}",
+                e
+            );
+            Err(ApiError::from_db(&e))
+        }
+    }
+}
+
+/// Credentials an ingest request presents, checked against every sensor it reports for
+struct IngestAuth<'a> {
+    headers: &'a HeaderMap,
+    body: SignedBody<'a>,
+}
+
+/// The body an X-Signature covers, as the transport makes it available
+enum SignedBody<'a> {
+    Whole(&'a [u8]),
+    Streamed(Option<&'a Mutex<BodyMac>>), // Keyed by the first sensor, verified once read
+    Frames,                               // WebSocket frames carry no headers to sign
+}
+
+/// A sensor whose API key the caller holds, only issued by `admit_sensor`, so no ingest
+/// path can process readings without going through it
+struct SensorGrant {
+    sensor_id: i32,
+}
+
+/// Authentication and integrity shared by every ingest path: the caller must hold the
+/// sensor's API key, and sign the body when sent or required by the sensor
+async fn admit_sensor(
+    state: &AppState,
+    auth: &IngestAuth<'_>,
+    sensor_id: i32,
+) -> Result<SensorGrant, ApiError> {
+    let credentials = authenticate_sensor(state, auth.headers, sensor_id).await?;
+    match auth.body {
+        SignedBody::Whole(body) => check_signature(auth.headers, &credentials, body)?,
+        SignedBody::Streamed(mac) => key_stream(mac, &credentials)?,
+        // Signed readings can only be sent over HTTP
+        SignedBody::Frames if credentials.require_signature => {
+            return Err(ApiError::SignedFramesUnsupported);
+        }
+        SignedBody::Frames => {}
+    }
+    Ok(SensorGrant { sensor_id })
+}
+
+/// Authenticates a sensor through its API key, checked against the stored hash
+async fn authenticate_sensor(
+    state: &AppState,
+    headers: &HeaderMap,
+    sensor_id: i32,
+) -> Result<db::SensorCredentials, ApiError> {
+    let Some(api_key) = header_str(headers, API_KEY_HEADER) else {
+        return Err(ApiError::MissingApiKey);
+    };
+    let credentials = match db::sensor_credentials(&state.pool, sensor_id).await {
+        Ok(Some(credentials)) => credentials,
+        Ok(None) => return Err(ApiError::SensorNotFound),
+        Err(e) => {
+            tracing::error!(sensor_id, "Error checking sensor API key: {}", e);
+            return Err(ApiError::from_db(&e));
+        }
+    };
+    let valid = credentials
+        .api_key_hash
+        .as_deref()
+        .is_some_and(|hash| crypto::verify_hash(&state.argon2, api_key, hash));
+    if !valid {
+        tracing::warn!(sensor_id, "Rejected ingest with an invalid API key");
+        return Err(ApiError::InvalidApiKey);
+    }
+    Ok(credentials)
+}
+
+/// Integrity: checks the body's HMAC when sent, which sensors requiring it must do
+fn check_signature(
+    headers: &HeaderMap,
+    credentials: &db::SensorCredentials,
+    body: &[u8],
+) -> Result<(), ApiError> {
+    let signature = match header_str(headers, SIGNATURE_HEADER) {
+        Some(signature) => signature,
+        None if credentials.require_signature => return Err(ApiError::MissingSignature),
+        None => return Ok(()),
+    };
+    let valid = credentials
+        .signing_secret
+        .as_deref()
+        .is_some_and(|secret| crypto::verify_signature(secret, body, signature));
+    if !valid {
+        return Err(ApiError::InvalidSignature);
+    }
+    Ok(())
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
 }
 
 pub async fn root() -> &'static str {
@@ -40,167 +321,1966 @@ pub async fn root() -> &'static str {
 }
 
 pub async fn db_health_check(State(state): State<AppState>) -> &'static str {
-    match db::health_check(&state.pool).await {
-        Ok(_) => "Database is up and running",
-        Err(_) => "Database is down",
+    let timeout = state.config.health_check_timeout;
+    match tokio::time::timeout(timeout, db::health_check(&state.pool)).await {
+        Ok(Ok(_)) => "Database is up and running",
+        Ok(Err(_)) => "Database is down",
+        Err(_) => {
+            tracing::warn!("Database health check timed out after {:?}", timeout);
+            "Database is down"
+        }
+    }
+}
+
+/// Solana RPC reachability and wallet funding, so monitoring can alert before proofs fail
+pub async fn solana_health_check(State(state): State<AppState>) -> impl IntoResponse {
+    if let Err(e) = state.client.test_connection().await {
+        tracing::warn!("Solana health check failed: {}", e);
+        let body = json!({ "rpc_reachable": false });
+        let resp = HttpResponse::service_unavailable("Solana RPC is unreachable");
+        return resp.with_data(body);
+    }
+
+    match state.client.balance().await {
+        Ok(balance) => {
+            let body = json!({
+                "rpc_reachable": true,
+                "balance_lamports": balance,
+                "balance_sol": lamports::format_sol(balance),
+                "min_balance_lamports": state.client.min_balance,
+                "enough_balance": state.client.funded(balance),
+            });
+            HttpResponse::success_data(body)
+        }
+        Err(e) => {
+            tracing::warn!("Solana balance check failed: {}", e);
+            let body = json!({ "rpc_reachable": false });
+            let resp = HttpResponse::service_unavailable("Solana wallet balance is unavailable");
+            resp.with_data(body)
+        }
     }
 }
 
+/// Prometheus scrape target, left unauthenticated for the scraper
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let content_type = (header::CONTENT_TYPE, "text/plain; version=0.0.4");
+    ([content_type], state.metrics.render())
+}
+
 pub async fn ingest_reading(
+    Query(query): Query<IngestQuery>,
     State(state): State<AppState>,
-    Json(payload): Json<SensorReading>,
-) -> impl IntoResponse {
-    // Validate payload: check for invalid values and missing fields
-    if let Err(reason) = db::validate_reading(&payload) {
-        return Json(HttpResponse::<()>::bad_request(reason)).into_response();
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    // Decode payload in the requested wire format (keyed JSON by default)
+    let payload = query.parse_reading(&body).map_err(|reason| {
+        telemetry::record_ingest("http", 0, 1);
+        ApiError::InvalidPayload { reason }
+    })?;
+
+    // Test mode lets integrators exercise the real server without creating data
+    if query.test && !state.config.features.ingest_test_mode {
+        return Err(ApiError::TestModeDisabled);
     }
 
-    // Access control: check if sensor exists
-    match db::sensor_exists(&state.pool, payload.sensor_id).await {
-        Ok(exists) => {
-            if exists == false {
-                let reason = "Sensor is not registered";
-                return Json(HttpResponse::<()>::bad_request(reason)).into_response();
+    // Authentication: the caller must hold the sensor's API key, and may sign the body
+    let auth = IngestAuth {
+        headers: &headers,
+        body: SignedBody::Whole(&body),
+    };
+    let grant = admit_sensor(&state, &auth, payload.sensor_id)
+        .await
+        .inspect_err(|_| telemetry::record_ingest("http", 0, 1))?;
+
+    let result = process_reading(&state, &grant, payload, query.test).await;
+    record_outcome("http", &result);
+    Ok(match result? {
+        Ingested::Recorded { receipt, timing } => {
+            let headers = [(HeaderName::from_static("server-timing"), timing.header())];
+            (headers, HttpResponse::created(receipt)).into_response()
+        }
+        Ingested::Coalesced => {
+            let body = json!({ "coalesced": true });
+            HttpResponse::success_data(body).into_response()
+        }
+        Ingested::AlreadyRecorded { signature } => {
+            let body = json!({ "already_recorded": true, "signature": signature });
+            HttpResponse::success_data(body).into_response()
+        }
+        Ingested::Simulated { receipt } => HttpResponse::success_data(receipt).into_response(),
+    })
+}
+
+/// Counts a single reading towards the ingest metrics, coalesced and simulated ones aside
+fn record_outcome(transport: &'static str, result: &Result<Ingested, ApiError>) {
+    match result {
+        Ok(Ingested::Recorded { .. }) => telemetry::record_ingest(transport, 1, 0),
+        Ok(_) => {}
+        Err(_) => telemetry::record_ingest(transport, 0, 1),
+    }
+}
+
+/// Outcome of a reading accepted by the ingest pipeline
+enum Ingested {
+    Recorded {
+        receipt: IngestReceipt, // Stored, its proof is queued
+        timing: IngestTiming,
+    },
+    Coalesced,
+    AlreadyRecorded {
+        signature: String,
+    }, // Retransmitted sequence number, nothing was stored
+    Simulated {
+        receipt: IngestReceipt, // Test mode, nothing was persisted nor anchored
+    },
+}
+
+/// Server-side duration of each ingest phase, returned so devices can adapt their cadence
+struct IngestTiming {
+    validate: Duration,
+    insert: Duration,
+}
+
+impl IngestTiming {
+    fn phases(&self) -> [(&'static str, f64); 2] {
+        [
+            ("validate", self.validate.as_secs_f64() * 1000.0),
+            ("insert", self.insert.as_secs_f64() * 1000.0),
+        ]
+    }
+
+    /// `Server-Timing` header value, in milliseconds
+    fn header(&self) -> String {
+        let phases = self
+            .phases()
+            .map(|(name, ms)| format!("{};dur={:.1}", name, ms));
+        phases.join(", ")
+    }
+
+    /// Per-phase milliseconds, for transports without headers
+    fn to_json(&self) -> Value {
+        let phases = self
+            .phases()
+            .map(|(name, ms)| (name.to_string(), json!(ms)));
+        Value::Object(phases.into_iter().collect())
+    }
+}
+
+/// Ingest pipeline shared by every transport: validation, coalescing, storage and a queued proof.
+/// In test mode, only the read-only checks run and the would-be memo is returned.
+async fn process_reading(
+    state: &AppState,
+    grant: &SensorGrant,
+    mut payload: SensorReading,
+    test: bool,
+) -> Result<Ingested, ApiError> {
+    let started = Instant::now();
+
+    // The reading must belong to the sensor the caller was admitted for
+    if payload.sensor_id != grant.sensor_id {
+        return Err(ApiError::ForeignReading);
+    }
+
+    // Anti-spam: cheap proof-of-work check before touching the database
+    if !crypto::valid_pow(&payload, state.config.pow_difficulty) {
+        return Err(ApiError::InvalidNonce);
+    }
+
+    // Flood protection: per-sensor token bucket, before any database or Solana work
+    check_rate(state, payload.sensor_id)?;
+
+    // Access control: check if sensor exists, loading its ingest settings
+    let settings = match db::ingest_settings(&state.pool, payload.sensor_id).await {
+        Ok(Some(settings)) => settings,
+        Ok(None) => return Err(ApiError::SensorNotFound),
+        Err(e) => {
+            tracing::error!(
+                sensor_id = payload.sensor_id,
+                "Error checking sensor existence: {}",
+                e
+            );
+            return Err(ApiError::from_db(&e));
+        }
+    };
+
+    if !settings.enabled {
+        return Err(ApiError::SensorDisabled);
+    }
+    if let Some(sensor_type) = state.blocked_type(&settings) {
+        tracing::warn!(
+            sensor_id = payload.sensor_id,
+            sensor_type,
+            "Rejected reading from sensor of blocked type"
+        );
+        return Err(ApiError::SensorTypeBlocked);
+    }
+
+    // Idempotency: a retransmitted sequence number is acknowledged without paying twice
+    if let Some(signature) = recorded_signature(state, &payload).await? {
+        return Ok(Ingested::AlreadyRecorded { signature });
+    }
+
+    // Validate payload: check for out-of-range values
+    let limits = &state.config.timestamp_limits;
+    db::validate_reading(&payload, &settings.bounds, limits)?;
+
+    // Past-dated readings: accept, reject or flag as backfilled
+    let past = &state.config.past_readings;
+    db::apply_past_reading_policy(&mut payload, &settings, past.policy, past.tolerance)?;
+    db::compensate_co2(&mut payload, &settings, &state.config.humidity_compensation);
+
+    // Ordering policy: monotonic time series for sensors that require it
+    if let Err(last_accepted) = db::check_ordering(&payload, &settings) {
+        return Err(ApiError::OutOfOrder { last_accepted });
+    }
+
+    // Coalescing: fold steady-state readings into the latest stored one
+    if !test {
+        let coalesce = &state.config.coalesce;
+        match db::coalesce_reading(&state.pool, &payload, coalesce.epsilon, coalesce.window).await {
+            Ok(true) => return Ok(Ingested::Coalesced),
+            Ok(false) => {}
+            Err(e) => {
+                tracing::error!(
+                    sensor_id = payload.sensor_id,
+                    "Error coalescing reading: {}",
+                    e
+                );
+                return Err(ApiError::from_db(&e));
             }
         }
+    }
+
+    // Sampling policy: enforce the sensor's minimum interval between readings
+    match db::next_allowed_reading(&state.pool, payload.sensor_id).await {
+        Ok(Some(next_allowed)) if payload.timestamp < next_allowed => {
+            return Err(ApiError::TooSoon { next_allowed });
+        }
+        Ok(_) => {}
         Err(e) => {
-            println!("Error checking sensor existence: {}", e);
-            return Json(HttpResponse::<()>::internal_error()).into_response();
+            tracing::error!(
+                sensor_id = payload.sensor_id,
+                "Error checking sensor sampling interval: {}",
+                e
+            );
+            return Err(ApiError::from_db(&e));
         }
     }
 
-    // Submit proof to Solana blockchain
-    let signature = match state.client.submit(&payload).await {
-        Ok(signature) => signature,
+    if test {
+        let receipt = IngestReceipt {
+            reading_id: None,
+            hash: crypto::reading_hash(&payload),
+            signature: None,
+            memo: Some(solana::memo(
+                &payload,
+                state.client.memo_version,
+                &settings.label,
+            )),
+            test: true,
+        };
+        return Ok(Ingested::Simulated { receipt });
+    }
+
+    let validated = Instant::now();
+
+    // Wallet depletion: refuse readings that couldn't be anchored anyway
+    if state.client.depleted() {
+        return Err(ApiError::WalletDepleted);
+    }
+
+    // Backpressure: reserve a queue slot first, so nothing is stored when the queue is full
+    let Ok(slot) = state.proofs.try_reserve() else {
+        tracing::warn!(
+            sensor_id = payload.sensor_id,
+            "Proof queue is full, rejecting reading"
+        );
+        return Err(ApiError::ProofQueueFull);
+    };
+
+    // Insert reading into DB, a concurrent retransmission may have won the race
+    let reading_id = match db::insert_reading(&state.pool, &payload, String::new()).await {
+        Ok(Some(reading_id)) => reading_id,
+        Ok(None) => {
+            let signature = recorded_signature(state, &payload)
+                .await?
+                .unwrap_or_default();
+            return Ok(Ingested::AlreadyRecorded { signature });
+        }
+        // PostgreSQL unique violation code, the sensor already reported this timestamp
+        Err(Error::Database(e)) if e.code() == Some(std::borrow::Cow::from("23505")) => {
+            tracing::warn!(sensor_id = payload.sensor_id, "Duplicate reading");
+            return Err(ApiError::DuplicateReading);
+        }
         Err(e) => {
-            println!("Error submitting reading to Solana: {}", e);
-            return Json(HttpResponse::<()>::internal_error()).into_response();
+            tracing::error!(
+                sensor_id = payload.sensor_id,
+                "Error inserting reading: {}",
+                e
+            );
+            return Err(ApiError::from_db(&e));
         }
     };
 
-    // Insert reading into DB
-    if let Err(e) = db::insert_reading(&state.pool, &payload, signature).await {
-        println!("Error inserting reading: {}", e);
-        return Json(HttpResponse::<()>::internal_error()).into_response();
+    // Alerting: record readings above the sensor's CO2 threshold, off the ingest path
+    if let Some(threshold) = settings.alert_threshold.filter(|t| payload.co2 > *t) {
+        let pool = state.pool.clone();
+        let (sensor_id, co2) = (payload.sensor_id, payload.co2);
+        tokio::spawn(async move {
+            if let Err(e) = db::record_alert(&pool, sensor_id, reading_id, co2, threshold).await {
+                tracing::error!(sensor_id, reading_id, "Error recording alert: {}", e);
+            }
+        });
     }
 
-    Json(HttpResponse::<()>::success()).into_response()
+    // Submit proof to Solana blockchain in the background
+    let receipt = IngestReceipt {
+        reading_id: Some(reading_id),
+        hash: crypto::reading_hash(&payload),
+        signature: None,
+        memo: None,
+        test: false,
+    };
+    slot.send(ProofJob {
+        reading_id,
+        reading: payload.clone(),
+        label: settings.label,
+    });
+
+    let timing = IngestTiming {
+        validate: validated - started,
+        insert: validated.elapsed(),
+    };
+
+    // Fan out to the sensor's forwarding endpoint, if any
+    if let Some(url) = settings.forward_url {
+        state.forwarder.forward(url, payload.clone());
+    }
+
+    // Notify live subscribers (no receivers is not an error)
+    let _ = state.readings.send(payload);
+
+    Ok(Ingested::Recorded { receipt, timing })
+}
+
+/// Takes a token from the sensor's bucket, answering 429 with a retry hint when empty
+fn check_rate(state: &AppState, sensor_id: i32) -> Result<(), ApiError> {
+    state
+        .rate_limiter
+        .check(sensor_id)
+        .map_err(|retry_after| ApiError::RateLimited { retry_after })
+}
+
+/// Signature of the reading already stored under the payload's sequence number, if any
+async fn recorded_signature(
+    state: &AppState,
+    payload: &SensorReading,
+) -> Result<Option<String>, ApiError> {
+    let Some(seq) = payload.seq else {
+        return Ok(None);
+    };
+    db::recorded_signature(&state.pool, payload.sensor_id, seq)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = payload.sensor_id,
+                "Error checking reading sequence number: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })
+}
+
+/// Bulk backfill from an NDJSON body (one reading per line), answered with a summary of
+/// what happened to each line. Live-data policies (coalescing, sampling interval) don't
+/// apply to backfilled history. Two modes are available:
+/// - `partial` (default): invalid lines are skipped and reported, valid ones are committed
+///   in chunks as the body streams in, so memory stays bounded and every chunk committed
+///   before a fatal error (e.g. database failure) remains stored
+/// - `atomic`: lines are validated first and nothing is stored unless all of them are
+///   valid, then every reading is committed in a single transaction
+///
+/// Either way proofs are queued once readings are committed, as on live ingest.
+///
+/// A signed body can only be verified once fully read, so signed streams must be atomic.
+pub async fn ingest_stream(
+    Query(query): Query<BatchQuery>,
+    Query(units): Query<UnitQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<HttpResponse<Value>, ApiError> {
+    let signature = header_str(&headers, SIGNATURE_HEADER);
+    if signature.is_some() && query.mode != BatchMode::Atomic {
+        return Err(ApiError::SignedStreamNotAtomic);
+    }
+    let mac = signature.map(|_| Mutex::new(BodyMac::default()));
+
+    // The body is never buffered whole, so its size is capped as it streams in
+    let limit = state.config.stream_body_limit;
+    let mut read = 0;
+    let stream = body.into_data_stream().map(|chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        read += chunk.len();
+        if read > limit {
+            return Err(std::io::Error::from(std::io::ErrorKind::FileTooLarge));
+        }
+        if let Some(mac) = &mac {
+            mac.lock().expect("body MAC lock poisoned").update(&chunk);
+        }
+        Ok(chunk)
+    });
+    let mut lines = StreamReader::new(stream).lines();
+
+    // Every stored reading needs a proof queue slot, so no chunk nor batch may outsize it
+    let queue_capacity = state.config.proof_queue_capacity;
+    let chunk_size = state.config.ingest_chunk_size.min(queue_capacity);
+    let max_atomic = MAX_ATOMIC_READINGS.min(queue_capacity);
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut pending = Vec::new(); // Atomic mode: readings stored once all are validated
+    let mut settings = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut summary = BatchSummary::default();
+    let mut line_no = 0;
+    let auth = IngestAuth {
+        headers: &headers,
+        body: SignedBody::Streamed(mac.as_ref()),
+    };
+
+    let failure = loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break None,
+            Err(e) if e.kind() == std::io::ErrorKind::FileTooLarge => {
+                break Some(ApiError::StreamTooLarge { limit });
+            }
+            Err(e) => {
+                let reason = format!("Error reading request body: {}", e);
+                break Some(ApiError::InvalidPayload { reason });
+            }
+        };
+        line_no += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        summary.received += 1;
+
+        let checked = match serde_json::from_str::<SensorReading>(&line) {
+            Ok(reading) => {
+                let checked =
+                    check_reading(&state, &auth, &units, &mut settings, &mut seen, reading);
+                checked.await
+            }
+            Err(e) => Err(LineFailure::Rejected(format!("invalid reading: {}", e))),
+        };
+        let reading = match checked {
+            Ok(Some(reading)) => reading,
+            Ok(None) => {
+                summary.duplicates += 1;
+                continue;
+            }
+            Err(LineFailure::Rejected(reason)) => {
+                summary.reject(Position::Line(line_no), &reason);
+                if query.mode == BatchMode::Atomic {
+                    let line = line_no;
+                    break Some(ApiError::RejectedLine { line, reason });
+                }
+                continue;
+            }
+            Err(LineFailure::Fatal(e)) => break Some(e),
+        };
+
+        if query.mode == BatchMode::Atomic {
+            if pending.len() == max_atomic {
+                break Some(ApiError::TooManyReadings {
+                    what: "Atomic batches",
+                    max: max_atomic,
+                });
+            }
+            pending.push(reading);
+            continue;
+        }
+
+        chunk.push(reading);
+        if chunk.len() == chunk_size {
+            let readings = std::mem::replace(&mut chunk, Vec::with_capacity(chunk_size));
+            let count = readings.len();
+            match store_readings(&state, &settings, readings).await {
+                Ok(stored) => summary.store(count, stored),
+                Err(e) => break Some(e),
+            }
+        }
+    };
+
+    // Integrity: the whole body was read, so its signature can be checked
+    let failure = match (failure, signature, &mac) {
+        (None, Some(signature), Some(mac)) => {
+            let valid = mac
+                .lock()
+                .expect("body MAC lock poisoned")
+                .verify(signature);
+            (!valid).then_some(ApiError::InvalidSignature)
+        }
+        (failure, _, _) => failure,
+    };
+
+    // Atomic mode stores every line or none, while partial mode also stores the valid
+    // readings preceding a failure
+    let remaining = match query.mode {
+        BatchMode::Atomic if failure.is_none() => pending,
+        BatchMode::Atomic => Vec::new(),
+        BatchMode::Partial => chunk,
+    };
+    let count = remaining.len();
+    let failure = match store_readings(&state, &settings, remaining).await {
+        Ok(stored) => {
+            summary.store(count, stored);
+            failure
+        }
+        Err(e) => failure.or(Some(e)),
+    };
+    tracing::info!("Streaming ingest stored {} readings", summary.accepted);
+    telemetry::record_ingest("stream", summary.accepted, summary.rejected);
+
+    // Report progress either way, so clients know where to resume
+    let summary = json!(summary);
+    match failure {
+        None => Ok(HttpResponse::success_data(summary)),
+        Some(e) => Ok(HttpResponse::error(e).or_data(summary)),
+    }
+}
+
+/// Flushes readings buffered by an offline sensor, sent as a JSON array. The batch is
+/// all-or-nothing: every reading is validated first and a single invalid one refuses the
+/// whole batch with 400, listing each rejected index. Otherwise all readings are stored
+/// with a single multi-row INSERT and their proofs queued. Retransmitted sequence numbers and duplicate
+/// timestamps are skipped.
+pub async fn ingest_batch(
+    Query(units): Query<UnitQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<HttpResponse<Value>, ApiError> {
+    // Parsed by hand, as the signature covers the raw body
+    let readings = serde_json::from_slice::<Vec<SensorReading>>(&body).map_err(|e| {
+        let reason = format!("Invalid batch: {}", e);
+        ApiError::InvalidPayload { reason }
+    })?;
+    // Every reading needs a proof queue slot, so no batch may outsize the queue
+    let max = MAX_BATCH_READINGS.min(state.config.proof_queue_capacity);
+    if readings.len() > max {
+        return Err(ApiError::TooManyReadings {
+            what: "Batches",
+            max,
+        });
+    }
+
+    let mut settings = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut summary = BatchSummary {
+        received: readings.len(),
+        ..Default::default()
+    };
+    let auth = IngestAuth {
+        headers: &headers,
+        body: SignedBody::Whole(&body),
+    };
+    let mut accepted = Vec::with_capacity(readings.len());
+    for (index, reading) in readings.into_iter().enumerate() {
+        match check_reading(&state, &auth, &units, &mut settings, &mut seen, reading).await {
+            Ok(Some(reading)) => accepted.push(reading),
+            Ok(None) => summary.duplicates += 1,
+            Err(LineFailure::Rejected(reason)) => summary.reject(Position::Index(index), &reason),
+            Err(LineFailure::Fatal(e)) => {
+                return Ok(HttpResponse::error(e).or_data(json!(summary)));
+            }
+        }
+    }
+    if summary.rejected > 0 {
+        telemetry::record_ingest("batch", 0, summary.rejected);
+        let e = ApiError::InvalidReadings {
+            count: summary.rejected,
+        };
+        return Ok(HttpResponse::error(e).with_data(json!(summary)));
+    }
+
+    // Insert readings into DB at once, concurrent retransmissions are skipped, then queue
+    // their proofs without waiting for Solana
+    let permits = match reserve_proofs(&state, accepted.len()) {
+        Ok(permits) => permits,
+        Err(e) => return Ok(HttpResponse::error(e).with_data(json!(summary))),
+    };
+    match db::insert_readings_batch(&state.pool, &accepted).await {
+        Ok(ids) => {
+            let count = accepted.len();
+            summary.store(count, queue_proofs(permits, &settings, accepted, ids));
+            telemetry::record_ingest("batch", summary.accepted, 0);
+            Ok(HttpResponse::success_data(json!(summary)))
+        }
+        Err(e) => {
+            tracing::error!("Error inserting readings batch: {}", e);
+            let resp = HttpResponse::error(ApiError::from_db(&e));
+            Ok(resp.with_data(json!(summary)))
+        }
+    }
+}
+
+/// Why a batch reading was not accepted
+enum LineFailure {
+    Rejected(String), // The line is invalid, the batch may go on
+    Fatal(ApiError),  // The batch can't go on (e.g. database error)
+}
+
+/// Validates a batch reading against its sensor's settings, loaded once per sensor along
+/// with its authentication and rate limiting, so a batch counts as a single request.
+/// Returns `None` for a retransmitted sequence number or a duplicate timestamp, either
+/// stored or `seen` earlier in the batch, which is skipped.
+async fn check_reading(
+    state: &AppState,
+    auth: &IngestAuth<'_>,
+    units: &UnitQuery,
+    settings: &mut HashMap<i32, db::IngestSettings>,
+    seen: &mut HashSet<(i32, DateTime<Utc>)>,
+    reading: SensorReading,
+) -> Result<Option<SensorReading>, LineFailure> {
+    // Converted first, as on single-reading ingest
+    let mut reading = units.to_celsius(reading).map_err(LineFailure::Rejected)?;
+    if !crypto::valid_pow(&reading, state.config.pow_difficulty) {
+        return Err(LineFailure::Rejected("invalid proof-of-work nonce".into()));
+    }
+
+    let sensor_settings = match settings.entry(reading.sensor_id) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            // Authentication first: the caller must hold the API key of every sensor it
+            // reports for, unknown ids failing alike so batches can't probe which exist
+            admit_sensor(state, auth, reading.sensor_id)
+                .await
+                .map_err(|e| match e {
+                    ApiError::SensorNotFound => LineFailure::Fatal(ApiError::InvalidApiKey),
+                    e => LineFailure::Fatal(e),
+                })?;
+            check_rate(state, reading.sensor_id).map_err(LineFailure::Fatal)?;
+
+            let sensor_settings = match db::ingest_settings(&state.pool, reading.sensor_id).await {
+                Ok(Some(sensor_settings)) => sensor_settings,
+                // Deleted since it was authenticated
+                Ok(None) => return Err(LineFailure::Rejected("sensor is not registered".into())),
+                Err(e) => {
+                    tracing::error!(
+                        sensor_id = reading.sensor_id,
+                        "Error loading sensor ingest settings: {}",
+                        e
+                    );
+                    return Err(LineFailure::Fatal(ApiError::from_db(&e)));
+                }
+            };
+            entry.insert(sensor_settings)
+        }
+    };
+    if !sensor_settings.enabled {
+        return Err(LineFailure::Rejected("sensor is disabled".into()));
+    }
+    if let Some(sensor_type) = state.blocked_type(sensor_settings) {
+        tracing::warn!(
+            sensor_id = reading.sensor_id,
+            sensor_type,
+            "Rejected reading from sensor of blocked type"
+        );
+        return Err(LineFailure::Rejected("sensor type is blocked".into()));
+    }
+    db::validate_reading(
+        &reading,
+        &sensor_settings.bounds,
+        &state.config.timestamp_limits,
+    )
+    .map_err(|e| LineFailure::Rejected(e.to_string()))?;
+
+    // Past-dated readings: accept, reject or flag as backfilled, as on live ingest
+    let past = &state.config.past_readings;
+    db::apply_past_reading_policy(&mut reading, sensor_settings, past.policy, past.tolerance)
+        .map_err(|e| match e {
+            ApiError::StaleReading => LineFailure::Rejected(e.to_string()),
+            e => LineFailure::Fatal(e),
+        })?;
+    db::compensate_co2(
+        &mut reading,
+        sensor_settings,
+        &state.config.humidity_compensation,
+    );
+
+    // Retransmitted sequence numbers are skipped, so interrupted backfills can be resent
+    if recorded_signature(state, &reading)
+        .await
+        .map_err(LineFailure::Fatal)?
+        .is_some()
+    {
+        return Ok(None);
+    }
+    if seen.contains(&(reading.sensor_id, reading.timestamp)) {
+        return Ok(None);
+    }
+    match db::reading_exists(&state.pool, reading.sensor_id, reading.timestamp).await {
+        Ok(true) => return Ok(None),
+        Ok(false) => {}
+        Err(e) => {
+            tracing::error!(
+                sensor_id = reading.sensor_id,
+                "Error checking for a duplicate reading: {}",
+                e
+            );
+            return Err(LineFailure::Fatal(ApiError::from_db(&e)));
+        }
+    }
+    if let Err(latest) = db::check_ordering(&reading, sensor_settings) {
+        let reason = format!("reading is not newer than {}", latest);
+        return Err(LineFailure::Rejected(reason));
+    }
+    if sensor_settings.strict_ordering {
+        sensor_settings.latest_reading = Some(reading.timestamp);
+    }
+    seen.insert((reading.sensor_id, reading.timestamp));
+
+    Ok(Some(reading))
+}
+
+/// Keys a signed stream's MAC with its sensor's secret, the signature being checked once
+/// the body is read. Unsigned streams are refused for sensors requiring a signature.
+fn key_stream(
+    mac: Option<&Mutex<BodyMac>>,
+    credentials: &db::SensorCredentials,
+) -> Result<(), ApiError> {
+    let Some(mac) = mac else {
+        if credentials.require_signature {
+            return Err(ApiError::MissingSignature);
+        }
+        return Ok(());
+    };
+    let Some(secret) = credentials.signing_secret.as_deref() else {
+        return Err(ApiError::InvalidSignature);
+    };
+    if !mac.lock().expect("body MAC lock poisoned").key(secret) {
+        return Err(ApiError::MixedSignedStream);
+    }
+    Ok(())
+}
+
+/// Reserves a proof queue slot for each reading about to be stored, so that, as on live
+/// ingest, nothing is stored when their proofs can't be queued or the wallet is depleted
+fn reserve_proofs(
+    state: &AppState,
+    count: usize,
+) -> Result<PermitIterator<'_, ProofJob>, ApiError> {
+    if state.client.depleted() {
+        return Err(ApiError::WalletDepleted);
+    }
+    state.proofs.try_reserve_many(count).map_err(|_| {
+        tracing::warn!(count, "Proof queue can't hold the batch, rejecting it");
+        ApiError::ProofQueueFull
+    })
+}
+
+/// Queues the proofs of stored readings, `ids` holding each reading's id, `None` when it
+/// was skipped as a duplicate. Returns how many were stored.
+fn queue_proofs(
+    permits: PermitIterator<'_, ProofJob>,
+    settings: &HashMap<i32, db::IngestSettings>,
+    readings: Vec<SensorReading>,
+    ids: Vec<Option<i32>>,
+) -> usize {
+    let stored = readings
+        .into_iter()
+        .zip(ids)
+        .filter_map(|(reading, id)| Some((reading, id?)));
+    let mut count = 0;
+    for ((reading, reading_id), slot) in stored.zip(permits) {
+        let label = settings[&reading.sensor_id].label.clone();
+        slot.send(ProofJob {
+            reading_id,
+            reading,
+            label,
+        });
+        count += 1;
+    }
+    count
+}
+
+/// Stores streamed readings in a single transaction, then queues their proofs. Solana is
+/// never waited for, so neither the transaction nor the request is held up by the RPC.
+async fn store_readings(
+    state: &AppState,
+    settings: &HashMap<i32, db::IngestSettings>,
+    readings: Vec<SensorReading>,
+) -> Result<usize, ApiError> {
+    if readings.is_empty() {
+        return Ok(0);
+    }
+    let permits = reserve_proofs(state, readings.len())?;
+    let ids = db::insert_readings_chunk(&state.pool, &readings)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error inserting readings chunk: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    Ok(queue_proofs(permits, settings, readings, ids))
+}
+
+/// Outcome of a batch ingest, line by line
+#[derive(Default, Serialize)]
+struct BatchSummary {
+    received: usize,
+    accepted: usize,
+    duplicates: usize, // Retransmitted sequence numbers, already stored
+    rejected: usize,
+    rejections: Vec<Rejection>, // The first MAX_REPORTED_REJECTIONS only
+}
+
+#[derive(Serialize)]
+struct Rejection {
+    #[serde(flatten)]
+    position: Position,
+    reason: String,
+}
+
+/// Where a rejected reading sits in the batch
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Position {
+    Line(usize),  // 1-based NDJSON line
+    Index(usize), // 0-based JSON array index
+}
+
+impl BatchSummary {
+    /// Counts a stored chunk, readings skipped by the INSERT being concurrent retransmissions
+    fn store(&mut self, count: usize, stored: usize) {
+        self.accepted += stored;
+        self.duplicates += count - stored;
+    }
+
+    fn reject(&mut self, position: Position, reason: &str) {
+        self.rejected += 1;
+        if self.rejections.len() < MAX_REPORTED_REJECTIONS {
+            let reason = reason.to_string();
+            self.rejections.push(Rejection { position, reason });
+        }
+    }
+}
+
+/// Long-lived ingest channel for always-connected devices: each text frame carries one
+/// reading and is answered with an ack frame holding the same body the HTTP endpoint returns
+pub async fn ingest_socket(
+    sensor_id: Path<i32>,
+    Query(units): Query<UnitQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    // Authentication: check the sensor's API key once, before upgrading the connection
+    let auth = IngestAuth {
+        headers: &headers,
+        body: SignedBody::Frames,
+    };
+    let grant = admit_sensor(&state, &auth, *sensor_id).await?;
+    Ok(ws.on_upgrade(move |socket| ingest_session(socket, state, grant, units)))
+}
+
+async fn ingest_session(
+    mut socket: WebSocket,
+    state: AppState,
+    grant: SensorGrant,
+    units: UnitQuery,
+) {
+    // Frames are processed one at a time: no new frame is read until the previous one is
+    // acked, so a slow pipeline pushes back on the device through the socket itself
+    while let Some(Ok(msg)) = socket.recv().await {
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue, // Pings are answered automatically, binary frames are ignored
+        };
+
+        let reading = serde_json::from_str::<SensorReading>(&text)
+            .map_err(|e| e.to_string())
+            .and_then(|reading| units.to_celsius(reading));
+        let ack = match reading {
+            Err(e) => {
+                telemetry::record_ingest("websocket", 0, 1);
+                let reason = format!("Invalid reading: {}", e);
+                json!(HttpResponse::error(ApiError::InvalidPayload { reason }))
+            }
+            Ok(reading) => {
+                let result = process_reading(&state, &grant, reading, false).await;
+                record_outcome("websocket", &result);
+                match result {
+                    Ok(Ingested::Recorded { receipt, timing }) => {
+                        json!(HttpResponse::created(json!({
+                            "queued": true,
+                            "receipt": receipt,
+                            "timing_ms": timing.to_json()
+                        })))
+                    }
+                    Ok(Ingested::Coalesced) => {
+                        json!(HttpResponse::success_data(json!({ "coalesced": true })))
+                    }
+                    Ok(Ingested::AlreadyRecorded { signature }) => {
+                        json!(HttpResponse::success_data(
+                            json!({ "already_recorded": true, "signature": signature })
+                        ))
+                    }
+                    Ok(Ingested::Simulated { receipt }) => {
+                        json!(HttpResponse::success_data(receipt))
+                    }
+                    Err(e) => json!(HttpResponse::error(e)),
+                }
+            }
+        };
+
+        if socket
+            .send(Message::Text(ack.to_string().into()))
+            .await
+            .is_err()
+        {
+            break; // Device disconnected
+        }
+    }
+}
+
+pub async fn stream_readings(
+    sensor_id: Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    // Forward readings of this sensor only, skipping any missed by a lagging subscriber
+    let sensor_id = *sensor_id;
+    let stream =
+        BroadcastStream::new(state.readings.subscribe()).filter_map(move |msg| match msg {
+            Ok(reading) if reading.sensor_id == sensor_id => {
+                Some(Event::default().event("reading").json_data(reading))
+            }
+            _ => None,
+        });
+
+    // Keep-alive comments prevent proxies from closing idle connections
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response())
 }
 
 pub async fn fetch_reading(
     sensor_id: Path<i32>,
     Query(range): Query<TimeRangeQuery>,
+    Query(status): Query<ProofStatusQuery>,
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
-) -> impl IntoResponse {
+) -> Result<HttpResponse<Vec<db::SensorReadingRecord>>, ApiError> {
     // Access control: check if user owns the sensor
-    match db::owns_sensor(&state.pool, claims.sub.clone(), *sensor_id).await {
-        Ok(ownership) => {
-            if ownership == false {
-                let msg = "Not authorized to access this sensor";
-                return Json(HttpResponse::<()>::forbidden(msg)).into_response();
-            }
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_raw_time_bounds()?;
+
+    let limit = state.row_limit();
+    // Admins bypass ownership, already checked for everyone else
+    let owner = (!claims.is_admin()).then(|| claims.sub.clone());
+    let proven = status.proven();
+    let readings = db::fetch_readings(&state.pool, *sensor_id, from, to, owner, proven, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching readings: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    if readings.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "readings" });
+    }
+    Ok(HttpResponse::success_data(readings))
+}
+
+/// Readings of several sensors in one round trip, keyed by sensor id. Like the sensors
+/// lookup, sensors the user doesn't own are skipped rather than refused.
+pub async fn fetch_readings_multi(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<MultiReadingsRequest>,
+) -> Result<HttpResponse<BTreeMap<i32, Vec<db::SensorReadingRecord>>>, ApiError> {
+    let ids = request.ids()?;
+    let (from, to) = request.range.to_raw_time_bounds()?;
+
+    let limit = state.row_limit();
+    // Admins see every sensor, everyone else only their own
+    let owner = (!claims.is_admin()).then(|| claims.sub.clone());
+    let readings = db::fetch_readings_multi(&state.pool, &ids, from, to, owner, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                username = %claims.sub,
+                sensors = ids.len(),
+                "Error fetching readings of several sensors: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    if readings.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "readings" });
+    }
+
+    let mut by_sensor: BTreeMap<i32, Vec<_>> = BTreeMap::new();
+    for reading in readings {
+        by_sensor
+            .entry(reading.sensor_id)
+            .or_default()
+            .push(reading);
+    }
+    Ok(HttpResponse::success_data(by_sensor))
+}
+
+/// Number of readings in the time range, so clients can size their UI or pagination.
+/// With `exact_count=false` it's the planner's estimate, unless that's small enough for
+/// an exact count to be cheap.
+pub async fn count_readings(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    Query(query): Query<CountQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<ReadingCount>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_time_bounds()?;
+
+    if !query.exact() {
+        let estimate = db::estimate_readings(&state.pool, *sensor_id, from, to)
+            .await
+            .map_err(|e| {
+                tracing::error!(
+                    sensor_id = *sensor_id,
+                    username = %claims.sub,
+                    "Error estimating readings: {}",
+                    e
+                );
+                ApiError::from_db(&e)
+            })?;
+        if estimate >= EXACT_COUNT_THRESHOLD {
+            let count = ReadingCount {
+                count: estimate,
+                approximate: true,
+            };
+            return Ok(HttpResponse::success_data(count));
         }
+    }
+
+    // Admins bypass ownership, already checked for everyone else
+    let owner = (!claims.is_admin()).then(|| claims.sub.clone());
+    let count = db::count_readings(&state.pool, *sensor_id, from, to, owner)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error counting readings: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    let count = ReadingCount {
+        count,
+        approximate: false,
+    };
+    Ok(HttpResponse::success_data(count))
+}
+
+/// Hourly or daily statistics of the readings in the time range, for dashboards
+pub async fn fetch_reading_aggregates(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    Query(query): Query<AggregateQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::ReadingAggregate>>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_time_bounds()?;
+
+    let limit = state.row_limit();
+    let bucket = query.bucket();
+    let aggregates = db::aggregate_readings(&state.pool, *sensor_id, from, to, bucket, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error aggregating readings: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    if aggregates.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "buckets" });
+    }
+    Ok(HttpResponse::success_data(aggregates))
+}
+
+pub async fn fetch_recent_readings(
+    sensor_id: Path<i32>,
+    Query(query): Query<RecentQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::SensorReadingRecord>>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let readings = db::fetch_recent_readings(&state.pool, *sensor_id, query.count())
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching recent readings: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    Ok(HttpResponse::success_data(readings))
+}
+
+pub async fn fetch_sensor(
+    sensor_id: Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<db::SensorDetail>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    match db::fetch_sensor(&state.pool, *sensor_id).await {
+        Ok(sensor) => Ok(HttpResponse::success_data(sensor)),
+        // Deleted since the access check
+        Err(Error::RowNotFound) => Err(ApiError::SensorNotFound),
         Err(e) => {
-            println!("Database error checking sensor ownership: {}", e);
-            return Json(HttpResponse::<()>::internal_error()).into_response();
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching sensor: {}",
+                e
+            );
+            Err(ApiError::from_db(&e))
         }
     }
+}
+
+/// Deletes a sensor. One with readings is only deleted, along with them, when `force` is set,
+/// and their on-chain proofs remain.
+pub async fn delete_sensor(
+    sensor_id: Path<i32>,
+    Query(query): Query<DeleteSensorQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
 
-    match db::fetch_readings(&state.pool, *sensor_id, range, claims.sub).await {
-        Ok(readings) => Json(HttpResponse::<_>::success_data(readings)).into_response(),
+    match db::delete_sensor(&state.pool, *sensor_id, query.force).await {
+        Ok(Some(readings)) => {
+            let body = json!({ "deleted_readings": readings });
+            Ok(HttpResponse::success_data(body))
+        }
+        Ok(None) => Err(ApiError::SensorHasReadings),
         Err(e) => {
-            println!("Error fetching readings: {}", e);
-            Json(HttpResponse::<()>::internal_error()).into_response()
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error deleting sensor: {}",
+                e
+            );
+            Err(ApiError::from_db(&e))
         }
     }
 }
 
+/// Streams the readings in the time range as a Parquet file. Errors past the headers can
+/// only be logged: the body is cut short and the missing footer makes the file unreadable.
+pub async fn export_readings_parquet(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_raw_time_bounds()?;
+
+    let sensor_id = *sensor_id;
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let readings = Box::pin(db::stream_readings(&state.pool, sensor_id, from, to));
+        if let Err(e) = export::write_readings_parquet(readings, writer).await {
+            tracing::error!(sensor_id, "Error exporting readings to Parquet: {}", e);
+        }
+    });
+
+    let disposition = format!("attachment; filename=\"sensor-{}.parquet\"", sensor_id);
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            "application/vnd.apache.parquet".to_string(),
+        ),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(reader))).into_response())
+}
+
+/// Streams the readings in the time range as CSV, for spreadsheets. As with Parquet,
+/// errors past the headers can only be logged and cut the file short.
+pub async fn export_readings_csv(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_raw_time_bounds()?;
+
+    let sensor_id = *sensor_id;
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let readings = Box::pin(db::stream_readings(&state.pool, sensor_id, from, to));
+        if let Err(e) = export::write_readings_csv(readings, writer).await {
+            tracing::error!(sensor_id, "Error exporting readings to CSV: {}", e);
+        }
+    });
+
+    let disposition = format!("attachment; filename=\"sensor-{}.csv\"", sensor_id);
+    let headers = [
+        (header::CONTENT_TYPE, "text/csv".to_string()),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(reader))).into_response())
+}
+
+pub async fn fetch_alerts(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::Alert>>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_time_bounds()?;
+
+    let limit = state.row_limit();
+    let alerts = db::fetch_alerts(&state.pool, *sensor_id, from, to, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching alerts: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    if alerts.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "alerts" });
+    }
+    Ok(HttpResponse::success_data(alerts))
+}
+
+pub async fn fetch_gaps(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    Query(gaps): Query<GapsQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::ReadingGap>>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_time_bounds()?;
+    let expected_interval = gaps.expected_interval()?;
+
+    let limit = state.row_limit();
+    let gaps = db::fetch_gaps(&state.pool, *sensor_id, from, to, expected_interval, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching reading gaps: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    if gaps.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "gaps" });
+    }
+    Ok(HttpResponse::success_data(gaps))
+}
+
+pub async fn fetch_missing_sequences(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::SequenceGap>>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_time_bounds()?;
+
+    let limit = state.row_limit();
+    let gaps = db::fetch_missing_sequences(&state.pool, *sensor_id, from, to, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching missing sequences: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    if gaps.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows {
+            what: "sequence gaps",
+        });
+    }
+    Ok(HttpResponse::success_data(gaps))
+}
+
+/// Composite health score over the time range, with freshness judged against `expected_interval`
+pub async fn fetch_sensor_health(
+    sensor_id: Path<i32>,
+    Query(range): Query<TimeRangeQuery>,
+    Query(gaps): Query<GapsQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<health::SensorHealth>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let (from, to) = range.to_time_bounds()?;
+    let expected_interval = gaps.expected_interval()?;
+
+    let stats = db::reading_stats(&state.pool, *sensor_id, from, to)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching sensor health: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    let weights = &state.config.health_weights;
+    let health = health::score(&stats, expected_interval, Utc::now(), weights);
+    Ok(HttpResponse::success_data(health))
+}
+
+/// Air quality index from the sensor's particulate matter averaged over the last 24 hours,
+/// the EPA averaging period for PM2.5 and PM10
+pub async fn fetch_sensor_aqi(
+    sensor_id: Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<aqi::AirQuality>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    let to = Utc::now();
+    let from = to - chrono::Duration::hours(24);
+    let averages = db::particulate_averages(&state.pool, *sensor_id, from, to)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error fetching particulate averages: {}",
+                e
+            );
+            ApiError::from_db(&e)
+        })?;
+    let air_quality = aqi::air_quality(&averages).ok_or(ApiError::NoParticulateReadings)?;
+    Ok(HttpResponse::success_data(air_quality))
+}
+
 pub async fn verify_reading(
     reading_id: Path<i32>,
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
-) -> impl IntoResponse {
-    match db::fetch_reading(&state.pool, *reading_id, claims.sub).await {
-        Ok(reading) => {
-            let signature = reading.tx_signature.clone();
-            let reading = SensorReading::from(reading);
-            // Verify proof on Solana blockchain
-            match state.client.verify(reading, signature).await {
-                Ok(result) => {
-                    let body = json!({ "verification": result });
-                    Json(HttpResponse::success_data(body)).into_response()
-                }
-                Err(e) => {
-                    println!("Error verifying reading: {}", e);
-                    Json(HttpResponse::<()>::internal_error()).into_response()
-                }
-            }
+) -> Result<HttpResponse<Value>, ApiError> {
+    verify_stored_reading(&state, *reading_id, None, &claims.sub).await
+}
+
+/// A single reading of the sensor, by id
+pub async fn fetch_sensor_reading(
+    Path((sensor_id, reading_id)): Path<(i32, i32)>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<db::SensorReadingRecord>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, sensor_id).await?;
+
+    match db::fetch_reading_by_id(&state.pool, sensor_id, reading_id).await {
+        Ok(Some(reading)) => Ok(HttpResponse::success_data(reading)),
+        Ok(None) => Err(ApiError::ReadingNotFound),
+        Err(e) => {
+            tracing::error!(
+                sensor_id,
+                reading_id,
+                username = %claims.sub,
+                "Error fetching reading: {}",
+                e
+            );
+            Err(ApiError::from_db(&e))
         }
-        Err(Error::RowNotFound) => {
-            // Failed sensor ownership access control also falls in this category
-            Json(HttpResponse::<()>::not_found()).into_response()
+    }
+}
+
+/// The sensor's most recent reading, for live overviews that don't need history
+pub async fn fetch_latest_reading(
+    Path(sensor_id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<db::SensorReadingRecord>, ApiError> {
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, sensor_id).await?;
+
+    match db::fetch_latest_reading(&state.pool, sensor_id).await {
+        Ok(Some(reading)) => Ok(HttpResponse::success_data(reading)),
+        Ok(None) => Err(ApiError::ReadingNotFound),
+        Err(e) => {
+            tracing::error!(
+                sensor_id,
+                username = %claims.sub,
+                "Error fetching latest reading: {}",
+                e
+            );
+            Err(ApiError::from_db(&e))
         }
+    }
+}
+
+/// The most recent reading of every sensor of the user, in a single request
+pub async fn fetch_latest_readings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::SensorReadingRecord>>, ApiError> {
+    let readings = db::fetch_latest_readings(&state.pool, claims.sub.clone(), state.row_limit())
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error fetching latest readings: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    if readings.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "sensors" });
+    }
+    Ok(HttpResponse::success_data(readings))
+}
+
+/// Same as `/verify/{reading_id}`, scoped to the sensor the reading belongs to
+pub async fn verify_sensor_reading(
+    Path((sensor_id, reading_id)): Path<(i32, i32)>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    verify_stored_reading(&state, reading_id, Some(sensor_id), &claims.sub).await
+}
+
+/// Checks the stored reading against the memo of its recorded transaction
+async fn verify_stored_reading(
+    state: &AppState,
+    reading_id: i32,
+    sensor_id: Option<i32>,
+    username: &str,
+) -> Result<HttpResponse<Value>, ApiError> {
+    let reading = match db::fetch_reading(&state.pool, reading_id, username.to_string()).await {
+        Ok(reading) => reading,
+        // Failed sensor ownership access control also falls in this category
+        Err(Error::RowNotFound) => return Err(ApiError::ReadingNotFound),
         Err(e) => {
-            println!("Database error in reading verification: {}", e);
-            Json(HttpResponse::<()>::internal_error()).into_response()
+            tracing::error!(
+                reading_id,
+                username,
+                "Database error in reading verification: {}",
+                e
+            );
+            return Err(ApiError::from_db(&e));
         }
+    };
+    if sensor_id.is_some_and(|id| id != reading.sensor_id) {
+        return Err(ApiError::ReadingNotFound);
     }
+    if reading.tx_signature.is_empty() {
+        let body = json!({ "verified": false, "pending": true });
+        return Ok(HttpResponse::success_data(body));
+    }
+
+    let signature = reading.tx_signature.clone();
+    let reading = SensorReading::from(reading);
+    // Verify proof on Solana blockchain
+    let result = state.client.verify(reading, signature).await.map_err(|e| {
+        tracing::error!(reading_id, username, "Error verifying reading: {}", e);
+        ApiError::Internal
+    })?;
+    let body = json!({ "verified": result.is_matched(), "verification": result });
+    Ok(HttpResponse::success_data(body))
 }
 
 pub async fn fetch_sensors(
+    Query(query): Query<SensorsQuery>,
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
-) -> impl IntoResponse {
-    match db::fetch_sensors(&state.pool, claims.sub).await {
-        Ok(sensors) => Json(HttpResponse::<_>::success_data(sensors)).into_response(),
+) -> Result<HttpResponse<Vec<db::Sensor>>, ApiError> {
+    // Hydrate a known set of sensors, skipping those the user doesn't own
+    if let Some(ids) = query.ids()? {
+        let sensors = db::fetch_sensors_by_ids(&state.pool, claims.sub.clone(), &ids)
+            .await
+            .map_err(|e| {
+                tracing::error!(username = %claims.sub, "Error fetching sensors by id: {}", e);
+                ApiError::from_db(&e)
+            })?;
+        return Ok(HttpResponse::success_data(sensors));
+    }
+
+    let sensors = db::fetch_sensors(&state.pool, claims.sub.clone(), state.row_limit())
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error fetching sensors: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    if sensors.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "sensors" });
+    }
+    Ok(HttpResponse::success_data(sensors))
+}
+
+/// Every sensor with its owner, restricted to admins
+pub async fn fetch_all_sensors(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::OwnedSensor>>, ApiError> {
+    require_admin(&claims)?;
+
+    let sensors = db::fetch_all_sensors(&state.pool, state.row_limit())
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error fetching all sensors: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    if sensors.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "sensors" });
+    }
+    Ok(HttpResponse::success_data(sensors))
+}
+
+/// Readings whose proof submission failed, most recent failure first, restricted to admins
+pub async fn fetch_dead_letters(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::DeadLetter>>, ApiError> {
+    require_admin(&claims)?;
+
+    let letters = db::fetch_dead_letters(&state.pool, state.row_limit())
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error fetching dead letters: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    if letters.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows {
+            what: "dead letters",
+        });
+    }
+    Ok(HttpResponse::success_data(letters))
+}
+
+/// Queues the proof of a dead-lettered reading again, restricted to admins. The entry is
+/// dropped once the proof is recorded, or updated if the submission fails again.
+pub async fn retry_dead_letter(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    require_admin(&claims)?;
+
+    let (reading, label) = match db::dead_letter_reading(&state.pool, id).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return Err(ApiError::DeadLetterNotFound),
         Err(e) => {
-            println!("Error fetching sensors: {}", e);
-            Json(HttpResponse::<()>::internal_error()).into_response()
+            tracing::error!(dead_letter = id, "Error fetching dead letter: {}", e);
+            return Err(ApiError::from_db(&e));
         }
+    };
+    // An earlier retry may have anchored it already, paying twice is pointless
+    if !reading.tx_signature.is_empty() {
+        return Err(ApiError::AlreadyAnchored);
+    }
+    if state.client.depleted() {
+        return Err(ApiError::WalletDepleted);
     }
+    let slot = state
+        .proofs
+        .try_reserve()
+        .map_err(|_| ApiError::ProofQueueFull)?;
+
+    let reading_id = reading.id;
+    tracing::info!(username = %claims.sub, reading_id, "Retrying dead-lettered proof");
+    slot.send(ProofJob {
+        reading_id,
+        reading: reading.into(),
+        label,
+    });
+    let body = json!({ "queued": true, "reading_id": reading_id });
+    Ok(HttpResponse::success_data(body))
 }
 
-pub async fn user_registry(
+/// Readings still awaiting their proof, oldest first, with their total count since the
+/// list stops at the row ceiling. Restricted to admins.
+pub async fn fetch_pending_proofs(
+    Query(query): Query<PendingProofsQuery>,
     State(state): State<AppState>,
-    Json(form): Json<UserForm>,
-) -> impl IntoResponse {
-    match db::register_user(&state.pool, form).await {
-        Ok(_) => Json(HttpResponse::<()>::success()).into_response(),
-        Err(sqlx::Error::Database(e)) => {
-            // PostgreSQL unique violation code
-            if e.code() == Some(std::borrow::Cow::from("23505")) {
-                println!("Username already taken");
-                Json(HttpResponse::<()>::conflicts("Username already taken")).into_response()
-            } else {
-                println!("Error in user registry: {}", e);
-                Json(HttpResponse::<()>::internal_error()).into_response()
-            }
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    require_admin(&claims)?;
+
+    let cutoff = query.cutoff();
+    let limit = state.max_rows() as i64;
+    let pending = db::fetch_pending_proofs(&state.pool, cutoff, limit);
+    let total = db::count_pending_proofs(&state.pool, cutoff);
+    let (pending, total) = tokio::try_join!(pending, total).map_err(|e| {
+        tracing::error!(username = %claims.sub, "Error fetching pending proofs: {}", e);
+        ApiError::from_db(&e)
+    })?;
+    let body = json!({ "total": total, "readings": pending });
+    Ok(HttpResponse::success_data(body))
+}
+
+/// Gives up on proofs pending for longer than the requested age, restricted to admins.
+/// The readings stay stored without a proof and their dead letters are dropped.
+pub async fn cancel_pending_proofs(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<CancelProofsRequest>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    require_admin(&claims)?;
+
+    let cancelled = db::cancel_pending_proofs(&state.pool, request.cutoff())
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error cancelling pending proofs: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    tracing::warn!(username = %claims.sub, cancelled, "Cancelled pending proofs");
+    Ok(HttpResponse::success_data(
+        json!({ "cancelled": cancelled }),
+    ))
+}
+
+/// Access control for administrative endpoints
+fn require_admin(claims: &Claims) -> Result<(), ApiError> {
+    match claims.is_admin() {
+        true => Ok(()),
+        false => Err(ApiError::AdminRequired),
+    }
+}
+
+pub async fn create_sensor(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(form): Json<SensorForm>,
+) -> Result<HttpResponse<NewSensor>, ApiError> {
+    for (field, value) in [("name", &form.name), ("location", &form.location)] {
+        check_sensor_field(field, value)?;
+    }
+
+    // Only the hash is stored, the key is shown this once
+    let api_key = crypto::generate_token();
+    let signing_secret = crypto::generate_token();
+    let sensor = db::create_sensor(
+        &state.pool,
+        &state.argon2,
+        claims.sub.clone(),
+        form,
+        &api_key,
+        &signing_secret,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!(username = %claims.sub, "Error creating sensor: {}", e);
+        ApiError::from_db(&e)
+    })?;
+    Ok(HttpResponse::success_data(NewSensor {
+        sensor,
+        api_key,
+        signing_secret,
+    }))
+}
+
+/// Renames or relocates a sensor
+pub async fn update_sensor(
+    sensor_id: Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(patch): Json<SensorPatch>,
+) -> Result<HttpResponse<db::Sensor>, ApiError> {
+    if patch.is_empty() {
+        return Err(ApiError::NothingToUpdate);
+    }
+    for (field, value) in [("name", &patch.name), ("location", &patch.location)] {
+        if let Some(value) = value {
+            check_sensor_field(field, value)?;
         }
+    }
+    if let Some(url) = patch.forward_url.as_deref().filter(|url| !url.is_empty()) {
+        forward::check_url(url)?;
+    }
+
+    // Access control: check if user owns the sensor
+    authorize_sensor(&state, &claims, *sensor_id).await?;
+
+    match db::update_sensor(&state.pool, *sensor_id, patch).await {
+        Ok(sensor) => Ok(HttpResponse::success_data(sensor)),
+        Err(Error::RowNotFound) => Err(ApiError::SensorNotFound),
         Err(e) => {
-            println!("Error in user registry: {}", e);
-            Json(HttpResponse::<()>::internal_error()).into_response()
+            tracing::error!(
+                sensor_id = *sensor_id,
+                username = %claims.sub,
+                "Error updating sensor: {}",
+                e
+            );
+            Err(ApiError::from_db(&e))
         }
     }
 }
 
-pub async fn user_login(
+/// Columns are VARCHAR(255)
+fn check_sensor_field(field: &'static str, value: &str) -> Result<(), ApiError> {
+    if value.trim().is_empty() || value.chars().count() > 255 {
+        return Err(ApiError::InvalidSensorField { field });
+    }
+    Ok(())
+}
+
+pub async fn fetch_sensors_activity(
+    Query(query): Query<ActivityQuery>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<Vec<db::SensorActivity>>, ApiError> {
+    let limit = state.row_limit();
+    let stale_first = query.stale_first();
+    let sensors = db::fetch_sensors_activity(&state.pool, claims.sub.clone(), stale_first, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error fetching sensors activity: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    if sensors.len() > state.max_rows() {
+        return Err(ApiError::TooManyRows { what: "sensors" });
+    }
+    Ok(HttpResponse::success_data(sensors))
+}
+
+pub async fn user_registry(
     State(state): State<AppState>,
     Json(form): Json<UserForm>,
-) -> impl IntoResponse {
-    match db::user_login(&state.pool, &form).await {
-        Ok(valid) => {
-            if valid {
-                let token = auth::create_jwt(&form.username);
-                let resp = LoginResponse::new(token, &form);
-                Json(HttpResponse::success_data(resp)).into_response()
-            } else {
-                Json(HttpResponse::<()>::unauthorized("Invalid credentials")).into_response()
-            }
+) -> Result<HttpResponse<()>, ApiError> {
+    db::validate_password(&form.username, &form.password)?;
+
+    match db::register_user(&state.pool, &state.argon2, form).await {
+        Ok(_) => Ok(HttpResponse::success()),
+        // PostgreSQL unique violation code
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23505") => {
+            tracing::warn!("Username already taken");
+            Err(ApiError::UsernameTaken)
         }
         Err(e) => {
-            println!("Error in user login: {}", e);
-            Json(HttpResponse::<()>::internal_error()).into_response()
+            tracing::error!("Error in user registry: {}", e);
+            Err(ApiError::from_db(&e))
         }
     }
 }
+
+/// Changes the caller's password once the current one is confirmed
+pub async fn change_password(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(form): Json<PasswordForm>,
+) -> Result<HttpResponse<()>, ApiError> {
+    db::validate_password(&claims.sub, &form.new_password)?;
+
+    // Confirm the current password, so a stolen session can't take over the account
+    let credentials = UserForm {
+        username: claims.sub.clone(),
+        password: form.old_password,
+    };
+    db::user_login(&state.pool, &state.argon2, &credentials)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error checking current password: {}", e);
+            ApiError::from_db(&e)
+        })?
+        .ok_or(ApiError::WrongPassword)?;
+
+    db::update_password(&state.pool, &state.argon2, &claims.sub, &form.new_password)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error changing password: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    Ok(HttpResponse::success())
+}
+
+/// Sets the forwarding target of the caller's sensors that have no `forward_url` of their own
+pub async fn update_forwarding(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(form): Json<UserForwarding>,
+) -> Result<HttpResponse<()>, ApiError> {
+    let forward_url = Some(form.forward_url.as_str()).filter(|url| !url.is_empty());
+    if let Some(url) = forward_url {
+        forward::check_url(url)?;
+    }
+
+    db::set_user_forward_url(&state.pool, &claims.sub, forward_url)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error setting forwarding target: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    Ok(HttpResponse::success())
+}
+
+/// Deletes the caller's account with every sensor and reading, once the password is confirmed.
+/// Proofs already anchored on-chain remain.
+pub async fn delete_account(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(form): Json<PasswordConfirmation>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    let credentials = UserForm {
+        username: claims.sub.clone(),
+        password: form.password,
+    };
+    db::user_login(&state.pool, &state.argon2, &credentials)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error checking password: {}", e);
+            ApiError::from_db(&e)
+        })?
+        .ok_or(ApiError::WrongPassword)?;
+
+    let (sensors, readings) = db::delete_user(&state.pool, &claims.sub)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error deleting user: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    let body = json!({ "deleted_sensors": sensors, "deleted_readings": readings });
+    Ok(HttpResponse::success_data(body))
+}
+
+pub async fn user_login(
+    State(state): State<AppState>,
+    Json(form): Json<UserForm>,
+) -> Result<HttpResponse<LoginResponse>, ApiError> {
+    let owner = db::user_login(&state.pool, &state.argon2, &form)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %form.username, "Error in user login: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    telemetry::record_login(owner.is_some());
+    let owner = owner.ok_or(ApiError::InvalidCredentials)?;
+
+    let refresh_token = crypto::generate_token();
+    let digest = crypto::token_digest(&refresh_token);
+    let expires_at = Utc::now() + state.config.jwt.refresh_expiry;
+    db::create_refresh_token(&state.pool, &form.username, &digest, expires_at)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %form.username, "Error issuing refresh token: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    let token = auth::create_jwt(&state.config.jwt, &owner).map_err(|e| {
+        tracing::error!(username = %owner.username, "Error signing token: {}", e);
+        ApiError::Internal
+    })?;
+    Ok(HttpResponse::success_data(LoginResponse::new(
+        token,
+        refresh_token,
+        owner,
+    )))
+}
+
+/// Issues a new access token in exchange for a valid refresh token
+pub async fn refresh_token(
+    State(state): State<AppState>,
+    Json(form): Json<RefreshForm>,
+) -> Result<HttpResponse<Value>, ApiError> {
+    let digest = crypto::token_digest(&form.refresh_token);
+    let owner = db::refresh_token_owner(&state.pool, &digest)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error checking refresh token: {}", e);
+            ApiError::from_db(&e)
+        })?
+        .ok_or(ApiError::InvalidRefreshToken)?;
+    let token = auth::create_jwt(&state.config.jwt, &owner).map_err(|e| {
+        tracing::error!(username = %owner.username, "Error signing token: {}", e);
+        ApiError::Internal
+    })?;
+    Ok(HttpResponse::success_data(json!({ "token": token })))
+}
+
+/// Revokes the refresh token, and the access token too when sent as a bearer token, so a
+/// leaked one stops working before it expires
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(form): Json<RefreshForm>,
+) -> Result<HttpResponse<()>, ApiError> {
+    if let Some(claims) = auth::bearer_claims(&state.config.jwt, &headers) {
+        let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+        db::revoke_access_token(&state.pool, &claims.jti, expires_at)
+            .await
+            .map_err(|e| {
+                tracing::error!(username = %claims.sub, "Error revoking access token: {}", e);
+                ApiError::from_db(&e)
+            })?;
+    }
+
+    let digest = crypto::token_digest(&form.refresh_token);
+    db::revoke_refresh_token(&state.pool, &digest)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error revoking refresh token: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    Ok(HttpResponse::success())
+}
+
+pub async fn logout_all(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<HttpResponse<()>, ApiError> {
+    db::bump_token_version(&state.pool, &claims.sub)
+        .await
+        .map_err(|e| {
+            tracing::error!(username = %claims.sub, "Error revoking user tokens: {}", e);
+            ApiError::from_db(&e)
+        })?;
+    Ok(HttpResponse::success())
+}
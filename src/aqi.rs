@@ -0,0 +1,214 @@
+use crate::db::ParticulateAverages;
+use serde::Serialize;
+
+/// Pollutants with an EPA AQI breakpoint table among the fields readings carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pollutant {
+    Pm25,
+    Pm10,
+}
+
+/// EPA health concern level of an index value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Category {
+    Good,
+    Moderate,
+    #[serde(rename = "Unhealthy for Sensitive Groups")]
+    UnhealthyForSensitiveGroups,
+    Unhealthy,
+    #[serde(rename = "Very Unhealthy")]
+    VeryUnhealthy,
+    Hazardous,
+}
+
+/// Concentration range (µg/m³) mapped linearly onto an index range
+struct Breakpoint {
+    c_lo: f64,
+    c_hi: f64,
+    i_lo: u16,
+    i_hi: u16,
+}
+
+const fn bp(c_lo: f64, c_hi: f64, i_lo: u16, i_hi: u16) -> Breakpoint {
+    Breakpoint {
+        c_lo,
+        c_hi,
+        i_lo,
+        i_hi,
+    }
+}
+
+/// 24-hour PM2.5 breakpoints, as revised by the EPA in 2024
+const PM25_BREAKPOINTS: [Breakpoint; 6] = [
+    bp(0.0, 9.0, 0, 50),
+    bp(9.1, 35.4, 51, 100),
+    bp(35.5, 55.4, 101, 150),
+    bp(55.5, 125.4, 151, 200),
+    bp(125.5, 225.4, 201, 300),
+    bp(225.5, 325.4, 301, 500),
+];
+
+/// 24-hour PM10 breakpoints
+const PM10_BREAKPOINTS: [Breakpoint; 6] = [
+    bp(0.0, 54.0, 0, 50),
+    bp(55.0, 154.0, 51, 100),
+    bp(155.0, 254.0, 101, 150),
+    bp(255.0, 354.0, 151, 200),
+    bp(355.0, 424.0, 201, 300),
+    bp(425.0, 604.0, 301, 500),
+];
+
+/// Highest value of the scale, concentrations beyond the last breakpoint are capped to it
+const MAX_AQI: u16 = 500;
+
+/// Index of one pollutant, from its average concentration over the window
+#[derive(Debug, Serialize)]
+pub struct PollutantIndex {
+    pollutant: Pollutant,
+    concentration: f64, // µg/m³
+    aqi: u16,
+    category: Category,
+}
+
+/// Overall index of a sensor, the worst of its pollutants, which is reported as dominant
+#[derive(Debug, Serialize)]
+pub struct AirQuality {
+    aqi: u16,
+    category: Category,
+    dominant: Pollutant,
+    pollutants: Vec<PollutantIndex>,
+}
+
+impl Category {
+    fn of(aqi: u16) -> Self {
+        match aqi {
+            0..=50 => Self::Good,
+            51..=100 => Self::Moderate,
+            101..=150 => Self::UnhealthyForSensitiveGroups,
+            151..=200 => Self::Unhealthy,
+            201..=300 => Self::VeryUnhealthy,
+            _ => Self::Hazardous,
+        }
+    }
+}
+
+/// EPA breakpoint formula: the concentration, truncated to the table's precision, is
+/// interpolated between the index bounds of the breakpoint containing it
+pub fn index(pollutant: Pollutant, concentration: f64) -> (u16, Category) {
+    let (breakpoints, concentration) = match pollutant {
+        Pollutant::Pm25 => (&PM25_BREAKPOINTS, (concentration * 10.0).floor() / 10.0),
+        Pollutant::Pm10 => (&PM10_BREAKPOINTS, concentration.floor()),
+    };
+    let concentration = concentration.max(0.0);
+
+    let aqi = match breakpoints.iter().find(|bp| concentration <= bp.c_hi) {
+        Some(bp) => {
+            let (i_lo, i_hi) = (f64::from(bp.i_lo), f64::from(bp.i_hi));
+            let aqi = (i_hi - i_lo) / (bp.c_hi - bp.c_lo) * (concentration - bp.c_lo) + i_lo;
+            aqi.round() as u16
+        }
+        None => MAX_AQI,
+    };
+    (aqi, Category::of(aqi))
+}
+
+/// Rates the averages of a window, `None` when it holds no particulate matter readings
+pub fn air_quality(averages: &ParticulateAverages) -> Option<AirQuality> {
+    let pollutants: Vec<PollutantIndex> = [
+        (Pollutant::Pm25, averages.pm25),
+        (Pollutant::Pm10, averages.pm10),
+    ]
+    .into_iter()
+    .filter_map(|(pollutant, concentration)| {
+        let concentration = concentration?;
+        let (aqi, category) = index(pollutant, concentration);
+        Some(PollutantIndex {
+            pollutant,
+            concentration,
+            aqi,
+            category,
+        })
+    })
+    .collect();
+
+    let dominant = pollutants.iter().max_by_key(|p| p.aqi)?;
+    Some(AirQuality {
+        aqi: dominant.aqi,
+        category: dominant.category,
+        dominant: dominant.pollutant,
+        pollutants,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pm25_breakpoint_edges() {
+        assert_eq!(index(Pollutant::Pm25, 0.0), (0, Category::Good));
+        assert_eq!(index(Pollutant::Pm25, 9.0), (50, Category::Good));
+        assert_eq!(index(Pollutant::Pm25, 9.1), (51, Category::Moderate));
+        assert_eq!(index(Pollutant::Pm25, 35.4), (100, Category::Moderate));
+        assert_eq!(
+            index(Pollutant::Pm25, 35.5),
+            (101, Category::UnhealthyForSensitiveGroups)
+        );
+        assert_eq!(index(Pollutant::Pm25, 55.5), (151, Category::Unhealthy));
+        assert_eq!(
+            index(Pollutant::Pm25, 225.4),
+            (300, Category::VeryUnhealthy)
+        );
+        assert_eq!(index(Pollutant::Pm25, 225.5), (301, Category::Hazardous));
+    }
+
+    #[test]
+    fn pm25_interpolates_within_breakpoint() {
+        // (100 - 51) / (35.4 - 9.1) * (20.0 - 9.1) + 51 = 71.3
+        assert_eq!(index(Pollutant::Pm25, 20.0), (71, Category::Moderate));
+    }
+
+    #[test]
+    fn concentration_truncated_before_lookup() {
+        // 9.09 would fall between the first two breakpoints without truncation
+        assert_eq!(index(Pollutant::Pm25, 9.09), (50, Category::Good));
+        assert_eq!(index(Pollutant::Pm10, 54.9), (50, Category::Good));
+        assert_eq!(index(Pollutant::Pm10, 55.0), (51, Category::Moderate));
+    }
+
+    #[test]
+    fn pm10_breakpoint_edges() {
+        assert_eq!(index(Pollutant::Pm10, 154.0), (100, Category::Moderate));
+        assert_eq!(index(Pollutant::Pm10, 255.0), (151, Category::Unhealthy));
+        assert_eq!(index(Pollutant::Pm10, 604.0), (500, Category::Hazardous));
+    }
+
+    #[test]
+    fn beyond_the_scale_capped() {
+        assert_eq!(index(Pollutant::Pm25, 900.0), (500, Category::Hazardous));
+        assert_eq!(index(Pollutant::Pm10, 1000.0), (500, Category::Hazardous));
+    }
+
+    #[test]
+    fn worst_pollutant_dominates() {
+        let averages = ParticulateAverages {
+            pm25: Some(12.0),
+            pm10: Some(200.0),
+        };
+        let air_quality = air_quality(&averages).unwrap();
+        assert_eq!(air_quality.dominant, Pollutant::Pm10);
+        assert_eq!(air_quality.aqi, 123);
+        assert_eq!(air_quality.category, Category::UnhealthyForSensitiveGroups);
+        assert_eq!(air_quality.pollutants.len(), 2);
+    }
+
+    #[test]
+    fn no_particulate_readings_no_index() {
+        let averages = ParticulateAverages {
+            pm25: None,
+            pm10: None,
+        };
+        assert!(air_quality(&averages).is_none());
+    }
+}
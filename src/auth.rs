@@ -1,11 +1,15 @@
+use crate::api::AppState;
+use crate::config::JwtSettings;
+use crate::db;
+use crate::db::TokenOwner;
 use axum::{
-    extract::Request,
+    extract::{Request, State},
     http::{HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
-use chrono::{Duration, Utc};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use chrono::Utc;
+use jsonwebtoken::{Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -13,55 +17,94 @@ pub struct Claims {
     pub sub: String,
     pub exp: i64,
     pub role: String,
+    pub ver: i32,    // Must match the user's stored token version
+    pub jti: String, // Identifies the token for individual revocation
 }
 
-pub fn create_jwt(username: impl AsRef<str>) -> String {
-    let expiration = Utc::now() + Duration::hours(1);
+/// Role granted access to every sensor, regardless of ownership
+const ADMIN_ROLE: &str = "admin";
+
+impl Claims {
+    pub fn is_admin(&self) -> bool {
+        self.role == ADMIN_ROLE
+    }
+}
+
+/// Signs a session token for the user, failures are left to the caller rather than panicking
+pub fn create_jwt(
+    settings: &JwtSettings,
+    owner: &TokenOwner,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let expiration = Utc::now() + settings.expiry;
     // Create claims object
     let claims = Claims {
-        sub: username.as_ref().to_string(),
+        sub: owner.username.clone(),
         exp: expiration.timestamp(),
-        role: "user".to_string(),
+        role: owner.role.clone(),
+        ver: owner.token_version,
+        jti: uuid::Uuid::new_v4().to_string(),
     };
-    // Load secret key from environment variable
-    let secret_key = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     // Generate JWT token
-    let token = encode(
-        &Header::default(),
+    encode(
+        &Header::new(settings.algorithm),
         &claims,
-        &EncodingKey::from_secret(secret_key.as_ref()),
+        &settings.encoding_key,
     )
-    .expect("JWT encoding failed");
+}
 
-    token
+/// Claims of the bearer token in the `Authorization` header, `None` if it is missing or
+/// doesn't validate. Revocation is not checked.
+pub fn bearer_claims(settings: &JwtSettings, headers: &HeaderMap) -> Option<Claims> {
+    // Extract authentication header from request
+    let auth_header = headers.get("Authorization").and_then(|v| v.to_str().ok())?;
+    // Remove "Bearer " prefix
+    let token = auth_header.strip_prefix("Bearer ")?;
+    // Current key, plus retired ones still honoured during a rotation overlap
+    let validation = Validation::new(settings.algorithm);
+    // Decode and validate token (checks expiration time and signature)
+    settings
+        .decoding_keys
+        .iter()
+        .find_map(|key| decode::<Claims>(token, key, &validation).ok())
+        .map(|token_data| token_data.claims)
 }
 
 pub async fn verify_jwt(
+    State(state): State<AppState>,
     headers: HeaderMap,
     mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    // Extract authentication header from request
-    let auth_header = headers
-        .get("Authorization")
-        .and_then(|v| v.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
-    // Check if it starts with "Bearer "
-    if !auth_header.starts_with("Bearer ") {
+    let claims = bearer_claims(&state.config.jwt, &headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    // Reject tokens revoked individually at logout
+    let revoked = db::token_revoked(&state.pool, &claims.jti)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                username = %claims.sub,
+                "Database error checking token revocation: {}",
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if revoked {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    // Reject tokens issued before the user's last global logout
+    let version = db::token_version(&state.pool, &claims.sub)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                username = %claims.sub,
+                "Database error checking token version: {}",
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if version != Some(claims.ver) {
         return Err(StatusCode::UNAUTHORIZED);
     }
-    // Remove "Bearer " prefix
-    let token = &auth_header[7..];
-    // Load secret key
-    let secret_key = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    // Decode and validate token (checks expiration time and signature)
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret_key.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| StatusCode::UNAUTHORIZED)?;
     // Add claims to request extensions so handlers can access them
-    request.extensions_mut().insert(token_data.claims);
+    request.extensions_mut().insert(claims);
     Ok(next.run(request).await)
 }
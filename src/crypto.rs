@@ -1,38 +1,168 @@
 use crate::db::SensorReading;
 use argon2::password_hash::SaltString;
-use argon2::password_hash::rand_core::OsRng;
-use argon2::{PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 
-pub fn calculate_hash(input: impl AsRef<str>) -> String {
+/// Hashes a password or key with the configured Argon2 cost parameters
+pub fn calculate_hash(argon2: &Argon2, input: impl AsRef<str>) -> String {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = argon2::Argon2::default();
     argon2
         .hash_password(input.as_ref().as_bytes(), &salt)
         .expect("Failed to hash password")
         .to_string()
 }
 
-pub fn verify_hash(password: &str, stored_hash: &str) -> bool {
+/// Fresh opaque refresh token: 256 random bits, hex encoded
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Digest stored in place of a refresh token, random tokens need no salt nor stretching
+pub fn token_digest(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
+
+/// Checks a password against its stored hash, whose own parameters apply, so hashes made
+/// before a cost change keep verifying
+pub fn verify_hash(argon2: &Argon2, password: &str, stored_hash: &str) -> bool {
     let parsed_hash = match PasswordHash::new(stored_hash) {
         Ok(hash) => hash,
         Err(_) => return false,
     };
 
     // Calculate Argon2 password hash
-    let argon2 = argon2::Argon2::default();
     argon2
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok()
 }
 
+/// Checks a hex-encoded HMAC-SHA256 of the raw body under the sensor's secret, in constant time
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature.trim()) else {
+        return false;
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// HMAC-SHA256 of a streamed body, whose key is only known once a reading names its
+/// sensor. Bytes read before then are buffered, so the signature covers the whole body.
+#[derive(Default)]
+pub struct BodyMac {
+    buffered: Vec<u8>,
+    mac: Option<Hmac<Sha256>>,
+}
+
+impl BodyMac {
+    pub fn update(&mut self, chunk: &[u8]) {
+        match &mut self.mac {
+            Some(mac) => mac.update(chunk),
+            None => self.buffered.extend_from_slice(chunk),
+        }
+    }
+
+    /// Keys the MAC with the sensor's secret, refused once it was keyed already
+    pub fn key(&mut self, secret: &str) -> bool {
+        if self.mac.is_some() {
+            return false;
+        }
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+        mac.update(&std::mem::take(&mut self.buffered));
+        self.mac = Some(mac);
+        true
+    }
+
+    /// Checks a hex-encoded signature of the body read so far, in constant time
+    pub fn verify(&self, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature.trim()) else {
+            return false;
+        };
+        self.mac
+            .clone()
+            .is_some_and(|mac| mac.verify_slice(&signature).is_ok())
+    }
+}
+
 pub fn reading_hash(reading: &SensorReading) -> String {
-    let data = format!(
+    reading_digest(reading).to_hex().to_string()
+}
+
+pub fn reading_digest(reading: &SensorReading) -> blake3::Hash {
+    let mut data = format!(
         "sensor:{}|ts:{}|co2:{:.2}|temp:{:.2}",
         reading.sensor_id,
         reading.timestamp.timestamp(),
         reading.co2,
         reading.temperature
     );
+    // Optional fields are only appended when present, so CO2-only readings keep their hash
+    let optional_fields = [
+        ("pressure", reading.pressure),
+        ("wind_speed", reading.wind_speed),
+        ("wind_dir", reading.wind_direction),
+        ("pm25", reading.pm25),
+        ("pm10", reading.pm10),
+        ("humidity", reading.humidity),
+    ];
+    for (field, value) in optional_fields {
+        if let Some(value) = value {
+            data.push_str(&format!("|{}:{:.2}", field, value));
+        }
+    }
+    blake3::hash(data.as_bytes())
+}
+
+/// Checks the reading's proof-of-work: `blake3(reading_hash || nonce)` must start with
+/// at least `difficulty` zero bits. A difficulty of zero disables the check.
+pub fn valid_pow(reading: &SensorReading, difficulty: u32) -> bool {
+    if difficulty == 0 {
+        return true;
+    }
+    let Some(nonce) = reading.nonce else {
+        return false;
+    };
+
+    let data = format!("{}{}", reading_hash(reading), nonce);
     let digest = blake3::hash(data.as_bytes());
-    digest.to_hex().to_string()
+    let mut zero_bits = 0;
+    for byte in digest.as_bytes() {
+        zero_bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zero_bits >= difficulty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::{Algorithm, Params, Version};
+
+    fn argon2(m_cost: u32, t_cost: u32, p_cost: u32) -> Argon2<'static> {
+        let params = Params::new(m_cost, t_cost, p_cost, None).unwrap();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    }
+
+    #[test]
+    fn hash_with_custom_costs_verifies() {
+        let custom = argon2(64, 3, 2);
+        let hash = calculate_hash(&custom, "s3cretpass");
+        assert!(hash.contains("m=64,t=3,p=2"));
+        assert!(verify_hash(&custom, "s3cretpass", &hash));
+        assert!(!verify_hash(&custom, "wrongpass1", &hash));
+    }
+
+    #[test]
+    fn hash_verifies_after_cost_change() {
+        let hash = calculate_hash(&argon2(64, 3, 2), "s3cretpass");
+        assert!(verify_hash(&argon2(128, 1, 1), "s3cretpass", &hash));
+    }
 }
@@ -1,13 +1,27 @@
+use super::config::HumidityCompensation;
 use super::crypto::{calculate_hash, verify_hash};
-use super::http::TimeRangeQuery;
-use chrono::{DateTime, Utc};
+use super::error::ApiError;
+use argon2::Argon2;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::postgres::PgExecutor;
+use sqlx::{FromRow, PgPool, QueryBuilder};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio_stream::Stream;
 
 pub async fn health_check(pool: &PgPool) -> Result<(), sqlx::Error> {
     sqlx::query("SELECT 1").fetch_one(pool).await.map(|_| ())
 }
 
+/// Schema names are interpolated into `SET search_path`, so only plain identifiers are allowed
+pub fn valid_schema_name(schema: &str) -> bool {
+    !schema.is_empty()
+        && schema.len() <= 63 // Postgres identifier limit
+        && schema.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && schema.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 /// Model used to represent a sensor record
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Sensor {
@@ -16,24 +30,184 @@ pub struct Sensor {
     location: String,
 }
 
+/// Model used to represent a sensor record along with its owner, for administrators
+#[derive(Debug, Serialize, FromRow)]
+pub struct OwnedSensor {
+    id: i32,
+    name: String,
+    location: String,
+    owner: String, // Username
+}
+
+/// Model used to represent a sensor record along with its data freshness
+#[derive(Debug, Serialize, FromRow)]
+pub struct SensorActivity {
+    id: i32,
+    name: String,
+    location: String,
+    last_reading_at: Option<DateTime<Utc>>, // None if the sensor never reported
+}
+
 /// Model used to represent a sensor reading
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SensorReading {
     pub(crate) sensor_id: i32,
     pub(crate) timestamp: DateTime<Utc>, // ISO 8601 format
     pub(crate) co2: f32,
     pub(crate) temperature: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pressure: Option<f32>, // hPa
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) wind_speed: Option<f32>, // m/s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) wind_direction: Option<f32>, // Degrees from north
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pm25: Option<f32>, // µg/m³
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pm10: Option<f32>, // µg/m³
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) humidity: Option<f32>, // Relative, %
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) co2_compensated: Option<f32>, // Computed on ingest, never sent by devices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) seq: Option<i64>, // Firmware sequence number, reveals dropped uploads
+    #[serde(default, skip_serializing)]
+    pub(crate) nonce: Option<u64>, // Anti-spam proof-of-work, not part of the reading itself
+    #[serde(skip)]
+    pub(crate) backfilled: bool, // Set by the past-dated reading policy, never by devices
 }
 
 /// Model used to represent a sensor in the database
+/// Measurements are `REAL` columns, which only decode into `f32` (`f64` needs `DOUBLE PRECISION`),
+/// so queries building this model use `query_as!` to have the mapping checked at compile time
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct SensorReadingRecord {
+    pub(crate) id: i32,
+    pub(crate) sensor_id: i32,
+    pub(crate) timestamp: DateTime<Utc>, // ISO 8601 format
+    pub(crate) co2: f32,                 // co2_level REAL
+    pub(crate) temperature: f32,         // temperature REAL
+    pub(crate) pressure: Option<f32>,    // pressure REAL, nullable like the other weather fields
+    pub(crate) wind_speed: Option<f32>,
+    pub(crate) wind_direction: Option<f32>,
+    pub(crate) pm25: Option<f32>, // pm25 REAL, nullable like the other air-quality fields
+    pub(crate) pm10: Option<f32>,
+    pub(crate) humidity: Option<f32>,
+    pub(crate) co2_compensated: Option<f32>, // Humidity-compensated CO2, next to the raw value
+    pub(crate) backfilled: bool,             // Past-dated reading accepted under the flag policy
+    pub(crate) tx_signature: String,
+}
+
+/// Model used to represent a reading whose proof submission failed
+#[derive(Debug, Serialize, FromRow)]
+pub struct DeadLetter {
     id: i32,
+    reading_id: i32,
+    sensor_id: i32,
+    timestamp: DateTime<Utc>, // Of the reading
+    reason: String,           // Error of the latest failed submission
+    attempts: i32,
+    failed_at: DateTime<Utc>,
+}
+
+/// Model used to represent a reading still awaiting its proof
+#[derive(Debug, Serialize, FromRow)]
+pub struct PendingProof {
+    reading_id: i32,
     sensor_id: i32,
-    timestamp: DateTime<Utc>, // ISO 8601 format
+    timestamp: DateTime<Utc>,
+    age_secs: i64,       // Since the reading's timestamp
+    dead_lettered: bool, // Its submission failed already
+}
+
+/// Model used to represent a run of sequence numbers never received
+#[derive(Debug, Serialize, FromRow)]
+pub struct SequenceGap {
+    first_missing: i64,
+    last_missing: i64,
+    missing: i64,
+}
+
+/// Name and location of a sensor being registered
+#[derive(Debug, Deserialize)]
+pub struct SensorForm {
+    pub name: String,
+    pub location: String,
+}
+
+/// Sensor metadata changes, absent fields are left untouched
+#[derive(Debug, Deserialize)]
+pub struct SensorPatch {
+    pub name: Option<String>,
+    pub location: Option<String>,
+    pub enabled: Option<bool>, // Disabled sensors can't ingest, e.g. during maintenance
+    pub forward_url: Option<String>, // Empty to stop forwarding, or fall back to the user's
+    pub humidity_compensation: Option<bool>,
+}
+
+impl SensorPatch {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.location.is_none()
+            && self.enabled.is_none()
+            && self.forward_url.is_none()
+            && self.humidity_compensation.is_none()
+    }
+}
+
+/// Account-wide forwarding target for sensors without their own, empty to clear it
+#[derive(Debug, Deserialize)]
+pub struct UserForwarding {
+    pub forward_url: String,
+}
+
+/// Aggregates over a sensor's readings in a time window, feeding its health score
+#[derive(Debug, FromRow)]
+pub struct ReadingStats {
+    pub(crate) readings: i64,
+    pub(crate) proven: i64,
+    pub(crate) outliers: i64, // Beyond 3 standard deviations of the window mean, in either field
+    pub(crate) sequenced: i64,
+    pub(crate) missing_sequences: i64,
+    pub(crate) last_reading_at: Option<DateTime<Utc>>,
+}
+
+/// Average particulate matter concentrations over a time window, rated by the AQI
+#[derive(Debug, FromRow)]
+pub struct ParticulateAverages {
+    pub(crate) pm25: Option<f64>, // µg/m³, None without PM2.5 readings in the window
+    pub(crate) pm10: Option<f64>, // µg/m³
+}
+
+/// Model used to represent the readings of a time bucket, averages are `DOUBLE PRECISION`
+#[derive(Debug, Serialize, FromRow)]
+pub struct ReadingAggregate {
+    bucket: DateTime<Utc>, // Start of the bucket, in UTC
+    readings: i64,
+    co2_avg: f64,
+    co2_min: f32,
+    co2_max: f32,
+    temperature_avg: f64,
+    temperature_min: f32,
+    temperature_max: f32,
+}
+
+/// Model used to represent a reading that crossed its sensor's CO2 alert threshold
+#[derive(Debug, Serialize, FromRow)]
+pub struct Alert {
+    id: i32,
+    reading_id: i32,
     co2: f32,
-    temperature: f32,
-    pub(crate) tx_signature: String,
+    threshold: f32,
+    created_at: DateTime<Utc>,
+}
+
+/// Model used to represent a period without readings
+#[derive(Debug, Serialize, FromRow)]
+pub struct ReadingGap {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    duration_secs: i64,
 }
 
 impl From<SensorReadingRecord> for SensorReading {
@@ -43,80 +217,439 @@ impl From<SensorReadingRecord> for SensorReading {
             co2: value.co2,
             timestamp: value.timestamp,
             temperature: value.temperature,
+            pressure: value.pressure,
+            wind_speed: value.wind_speed,
+            wind_direction: value.wind_direction,
+            pm25: value.pm25,
+            pm10: value.pm10,
+            humidity: value.humidity,
+            co2_compensated: value.co2_compensated,
+            seq: None,
+            nonce: None,
+            backfilled: value.backfilled,
         }
     }
 }
 
+/// Shortest password accepted at registration, in characters
+const MIN_PASSWORD_LEN: usize = 8;
+
 #[derive(Debug, Deserialize)]
 pub struct UserForm {
     pub username: String,
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PasswordForm {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Current password, re-entered to confirm a destructive account operation
+#[derive(Debug, Deserialize)]
+pub struct PasswordConfirmation {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshForm {
+    pub refresh_token: String,
+}
+
+/// Identity embedded in the tokens issued to a user
+#[derive(Debug, FromRow)]
+pub struct TokenOwner {
+    pub(crate) username: String,
+    pub(crate) token_version: i32,
+    pub(crate) role: String,
+}
+
+#[derive(Debug, FromRow)]
+struct UserRecord {
+    password: String, // Argon2 hash
+    #[sqlx(flatten)]
+    owner: TokenOwner,
+}
+
+/// Global validation ranges, used when a sensor doesn't define its own
+const DEFAULT_CO2_RANGE: (f32, f32) = (0.0, 100_000.0); // ppm
+const DEFAULT_TEMPERATURE_RANGE: (f32, f32) = (-100.0, 200.0); // Celsius
+
+/// Physical ranges of the optional weather fields
+const PRESSURE_RANGE: (f32, f32) = (300.0, 1100.0); // hPa
+const WIND_SPEED_RANGE: (f32, f32) = (0.0, 120.0); // m/s
+const WIND_DIRECTION_RANGE: (f32, f32) = (0.0, 360.0); // Degrees
+
+/// Physical ranges of the optional air-quality fields
+const PM_RANGE: (f32, f32) = (0.0, 1000.0); // µg/m³, PM2.5 and PM10 alike
+const HUMIDITY_RANGE: (f32, f32) = (0.0, 100.0); // %
+
+/// Per-sensor validation ranges, unset bounds fall back to the global defaults
+#[derive(Debug, Serialize, FromRow)]
+pub struct ReadingBounds {
+    co2_min: Option<f32>,
+    co2_max: Option<f32>,
+    temperature_min: Option<f32>,
+    temperature_max: Option<f32>,
+}
+
+/// How far a reading's timestamp may stray from the server clock before the sensor's
+/// clock is presumed wrong
+#[derive(Debug)]
+pub struct TimestampLimits {
+    pub max_age: Duration,  // Into the past
+    pub max_skew: Duration, // Into the future
+}
+
+/// Sensor identification included in richer on-chain memos
+#[derive(Debug, Clone, FromRow)]
+pub struct SensorLabel {
+    pub(crate) name: String,
+    pub(crate) location: String,
+}
+
+/// Per-sensor settings applied when ingesting its readings
 #[derive(Debug, FromRow)]
-struct UserRecord(String); // Tuple struct
+pub struct IngestSettings {
+    pub(crate) enabled: bool,
+    pub(crate) past_reading_policy: Option<String>, // Overrides the global policy when set
+    pub(crate) sensor_type: Option<String>,
+    pub(crate) strict_ordering: bool,
+    pub(crate) latest_reading: Option<DateTime<Utc>>, // Only loaded under strict ordering
+    #[sqlx(flatten)]
+    pub(crate) bounds: ReadingBounds,
+    #[sqlx(flatten)]
+    pub(crate) label: SensorLabel,
+    pub(crate) forward_url: Option<String>, // The sensor's own, else its user's
+    pub(crate) alert_threshold: Option<f32>,
+    pub(crate) humidity_compensation: bool,
+}
+
+/// Model used to represent a sensor with its full configuration
+#[derive(Debug, Serialize, FromRow)]
+pub struct SensorDetail {
+    id: i32,
+    name: String,
+    location: String,
+    enabled: bool,
+    #[sqlx(flatten)]
+    bounds: ReadingBounds,
+}
+
+pub fn validate_reading(
+    payload: &SensorReading,
+    bounds: &ReadingBounds,
+    limits: &TimestampLimits,
+) -> Result<(), ApiError> {
+    if payload.seq.is_some_and(|seq| seq < 0) {
+        return Err(ApiError::InvalidSeq);
+    }
+    check_timestamp(payload.timestamp, limits, Utc::now())?;
+    check_range(
+        "co2",
+        payload.co2,
+        bounds.co2_min.unwrap_or(DEFAULT_CO2_RANGE.0),
+        bounds.co2_max.unwrap_or(DEFAULT_CO2_RANGE.1),
+    )?;
+    check_range(
+        "temperature",
+        payload.temperature,
+        bounds
+            .temperature_min
+            .unwrap_or(DEFAULT_TEMPERATURE_RANGE.0),
+        bounds
+            .temperature_max
+            .unwrap_or(DEFAULT_TEMPERATURE_RANGE.1),
+    )?;
+    let optional_fields = [
+        ("pressure", payload.pressure, PRESSURE_RANGE),
+        ("wind_speed", payload.wind_speed, WIND_SPEED_RANGE),
+        (
+            "wind_direction",
+            payload.wind_direction,
+            WIND_DIRECTION_RANGE,
+        ),
+        ("pm25", payload.pm25, PM_RANGE),
+        ("pm10", payload.pm10, PM_RANGE),
+        ("humidity", payload.humidity, HUMIDITY_RANGE),
+    ];
+    for (field, value, (min, max)) in optional_fields {
+        if let Some(value) = value {
+            check_range(field, value, min, max)?;
+        }
+    }
+    Ok(())
+}
+
+/// Handling of readings timestamped further in the past than the configured tolerance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PastReadingPolicy {
+    Accept, // Stored like any other reading
+    Reject, // Refused, the device must not send stale data
+    Flag,   // Stored, marked as backfilled
+}
+
+impl FromStr for PastReadingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(Self::Accept),
+            "reject" => Ok(Self::Reject),
+            "flag" => Ok(Self::Flag),
+            other => Err(format!("Invalid past reading policy: {}", other)),
+        }
+    }
+}
+
+/// Applies the sensor's past-dated reading policy (or the global one) to a reading older
+/// than `tolerance`, flagging it as backfilled or rejecting it
+pub fn apply_past_reading_policy(
+    payload: &mut SensorReading,
+    settings: &IngestSettings,
+    global: PastReadingPolicy,
+    tolerance: Duration,
+) -> Result<(), ApiError> {
+    if payload.timestamp >= Utc::now() - tolerance {
+        return Ok(());
+    }
+    let policy = match settings.past_reading_policy.as_deref().map(str::parse) {
+        Some(Ok(policy)) => policy,
+        Some(Err(e)) => {
+            // Ruled out by the column's CHECK constraint
+            tracing::error!("Stored past reading policy is invalid: {}", e);
+            return Err(ApiError::Internal);
+        }
+        None => global,
+    };
+    match policy {
+        PastReadingPolicy::Accept => Ok(()),
+        PastReadingPolicy::Reject => Err(ApiError::StaleReading),
+        PastReadingPolicy::Flag => {
+            payload.backfilled = true;
+            Ok(())
+        }
+    }
+}
+
+/// Stores the humidity-compensated CO2 of a reading from a sensor opting into it,
+/// readings without humidity are left uncompensated
+pub fn compensate_co2(
+    payload: &mut SensorReading,
+    settings: &IngestSettings,
+    compensation: &HumidityCompensation,
+) {
+    if !settings.humidity_compensation {
+        return;
+    }
+    payload.co2_compensated = payload
+        .humidity
+        .map(|humidity| compensation.compensate(payload.co2, humidity));
+}
+
+/// Under strict ordering, a reading must be newer than the sensor's latest one,
+/// whose timestamp is returned on violation
+pub fn check_ordering(
+    payload: &SensorReading,
+    settings: &IngestSettings,
+) -> Result<(), DateTime<Utc>> {
+    match settings.latest_reading {
+        Some(latest) if settings.strict_ordering && payload.timestamp <= latest => Err(latest),
+        _ => Ok(()),
+    }
+}
 
-pub fn validate_reading(payload: &SensorReading) -> Result<(), &'static str> {
-    if payload.co2 < 0.0 {
-        return Err("Invalid CO2 value");
+/// Password strength rules enforced at registration and on password changes
+pub fn validate_password(username: &str, password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::PasswordTooShort {
+            min_len: MIN_PASSWORD_LEN,
+        });
+    }
+    if !password.chars().any(char::is_alphabetic) || !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err(ApiError::PasswordTooSimple);
+    }
+    if password == username {
+        return Err(ApiError::PasswordIsUsername);
+    }
+    Ok(())
+}
+
+/// Refuses timestamps outside the window around `now`, whose edges are still accepted
+fn check_timestamp(
+    timestamp: DateTime<Utc>,
+    limits: &TimestampLimits,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    if timestamp < now - limits.max_age {
+        return Err(ApiError::TimestampTooOld {
+            max_age_days: limits.max_age.num_days(),
+        });
+    }
+    if timestamp > now + limits.max_skew {
+        return Err(ApiError::TimestampInFuture);
+    }
+    Ok(())
+}
+
+/// Reports which bound a field violated, if any
+fn check_range(field: &'static str, value: f32, min: f32, max: f32) -> Result<(), ApiError> {
+    if value.is_nan() {
+        return Err(ApiError::InvalidValue { field });
+    }
+    if value < min {
+        return Err(ApiError::BelowMinimum { field, min });
+    }
+    if value > max {
+        return Err(ApiError::AboveMaximum { field, max });
     }
-    // TODO - validate the other fields
     Ok(())
 }
 
+/// Stores a reading, returning its id or `None` if its sequence number was already recorded.
+/// An empty `tx_signature` marks a reading whose proof is still pending.
 pub async fn insert_reading(
-    pool: &PgPool,
+    executor: impl PgExecutor<'_>,
     payload: &SensorReading,
     tx_signature: String,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<Option<i32>, sqlx::Error> {
+    let reading_id = sqlx::query_scalar!(
         r#"
-        INSERT INTO readings (sensor_id, timestamp, co2_level, temperature, tx_signature)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO readings (
+            sensor_id,
+            timestamp,
+            co2_level,
+            temperature,
+            pressure,
+            wind_speed,
+            wind_direction,
+            pm25,
+            pm10,
+            humidity,
+            co2_compensated,
+            tx_signature,
+            seq,
+            backfilled
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+        ON CONFLICT (sensor_id, seq) DO NOTHING
+        RETURNING id
         "#,
         payload.sensor_id,
         payload.timestamp,
         payload.co2,
         payload.temperature,
+        payload.pressure,
+        payload.wind_speed,
+        payload.wind_direction,
+        payload.pm25,
+        payload.pm10,
+        payload.humidity,
+        payload.co2_compensated,
+        tx_signature,
+        payload.seq,
+        payload.backfilled
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    if reading_id.is_some() {
+        tracing::info!(
+            sensor_id = payload.sensor_id,
+            "Inserted reading: {:?}",
+            payload
+        );
+    }
+    Ok(reading_id)
+}
+
+/// Records the proof of a reading stored while its submission was pending
+pub async fn set_reading_signature(
+    pool: &PgPool,
+    reading_id: i32,
+    tx_signature: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE readings
+        SET tx_signature = $2
+        WHERE id = $1
+        "#,
+        reading_id,
         tx_signature
     )
     .execute(pool)
     .await?;
 
-    println!("Inserted reading: {:?}", payload);
     Ok(())
 }
 
-pub async fn fetch_sensors(pool: &PgPool, username: String) -> Result<Vec<Sensor>, sqlx::Error> {
-    // Read from DB
-    let sensors = sqlx::query_as::<_, Sensor>(
+/// Records a failed proof submission, counting the attempts of a reading failing again
+pub async fn record_dead_letter(
+    pool: &PgPool,
+    reading_id: i32,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO dead_letters (reading_id, reason)
+        VALUES ($1, $2)
+        ON CONFLICT (reading_id) DO UPDATE
+        SET reason = EXCLUDED.reason,
+            attempts = dead_letters.attempts + 1,
+            failed_at = NOW()
+        "#,
+        reading_id,
+        reason
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Drops the dead letter of a reading whose proof was finally recorded, if any
+pub async fn clear_dead_letter(pool: &PgPool, reading_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM dead_letters WHERE reading_id = $1", reading_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Failed proof submissions, most recent failure first
+pub async fn fetch_dead_letters(pool: &PgPool, limit: i64) -> Result<Vec<DeadLetter>, sqlx::Error> {
+    let letters = sqlx::query_as!(
+        DeadLetter,
         r#"
         SELECT
-            s.id,
-            s.name,
-            s.location
-        FROM sensors s
-        INNER JOIN users u ON s.user_id = u.id
-        WHERE u.username = $1
-        ORDER BY s.name ASC
+            d.id,
+            d.reading_id,
+            r.sensor_id,
+            r.timestamp,
+            d.reason,
+            d.attempts,
+            d.failed_at
+        FROM dead_letters d
+        INNER JOIN readings r ON d.reading_id = r.id
+        ORDER BY d.failed_at DESC
+        LIMIT $1
         "#,
+        limit
     )
-    .bind(username)
     .fetch_all(pool)
     .await?;
-    Ok(sensors)
+
+    Ok(letters)
 }
 
-pub async fn fetch_readings(
+/// The reading of a dead letter with its sensor's label, to queue its proof again
+pub async fn dead_letter_reading(
     pool: &PgPool,
-    sensor_id: i32,
-    time_query: TimeRangeQuery,
-    username: String,
-) -> Result<Vec<SensorReadingRecord>, sqlx::Error> {
-    // Extract DateTime from query
-    let timestamp = time_query.to_cutoff_time();
-    // Read from DB
-    let readings = sqlx::query_as::<_, SensorReadingRecord>(
+    id: i32,
+) -> Result<Option<(SensorReadingRecord, SensorLabel)>, sqlx::Error> {
+    let row = sqlx::query!(
         r#"
         SELECT
             r.id,
@@ -124,95 +657,155 @@ pub async fn fetch_readings(
             r.timestamp,
             r.co2_level as co2,
             r.temperature,
-            r.tx_signature
-        FROM readings r
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature,
+            s.name as "name!",
+            s.location as "location!"
+        FROM dead_letters d
+        INNER JOIN readings r ON d.reading_id = r.id
         INNER JOIN sensors s ON r.sensor_id = s.id
-        INNER JOIN users u ON s.user_id = u.id
-        WHERE r.sensor_id = $1
-        AND u.username = $2
-        AND r.timestamp >= $3
-        ORDER BY r.timestamp ASC
+        WHERE d.id = $1
         "#,
+        id
     )
-    .bind(sensor_id)
-    .bind(username)
-    .bind(timestamp)
-    .fetch_all(pool)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(readings)
+    Ok(row.map(|r| {
+        let reading = SensorReadingRecord {
+            id: r.id,
+            sensor_id: r.sensor_id,
+            timestamp: r.timestamp,
+            co2: r.co2,
+            temperature: r.temperature,
+            pressure: r.pressure,
+            wind_speed: r.wind_speed,
+            wind_direction: r.wind_direction,
+            pm25: r.pm25,
+            pm10: r.pm10,
+            humidity: r.humidity,
+            co2_compensated: r.co2_compensated,
+            backfilled: r.backfilled,
+            tx_signature: r.tx_signature,
+        };
+        let label = SensorLabel {
+            name: r.name,
+            location: r.location,
+        };
+        (reading, label)
+    }))
 }
 
-pub async fn fetch_reading(
+/// Readings without a proof timestamped before `cutoff`, oldest first, cancelled ones aside
+pub async fn fetch_pending_proofs(
     pool: &PgPool,
-    reading_id: i32,
-    username: String,
-) -> Result<SensorReadingRecord, sqlx::Error> {
-    let reading = sqlx::query_as::<_, SensorReadingRecord>(
+    cutoff: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<PendingProof>, sqlx::Error> {
+    let pending = sqlx::query_as!(
+        PendingProof,
         r#"
         SELECT
-            r.id,
+            r.id as reading_id,
             r.sensor_id,
             r.timestamp,
-            r.co2_level as co2,
-            r.temperature,
-            r.tx_signature
+            EXTRACT(EPOCH FROM NOW() - r.timestamp)::BIGINT as "age_secs!",
+            d.id IS NOT NULL as "dead_lettered!"
         FROM readings r
-        INNER JOIN sensors s ON r.sensor_id = s.id
-        INNER JOIN users u ON s.user_id = u.id
-        WHERE r.id = $1
-        AND u.username = $2
+        LEFT JOIN dead_letters d ON d.reading_id = r.id
+        WHERE r.tx_signature = ''
+        AND r.proof_cancelled_at IS NULL
+        AND r.timestamp < $1
+        ORDER BY r.timestamp
+        LIMIT $2
         "#,
+        cutoff,
+        limit
     )
-    .bind(reading_id)
-    .bind(username)
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await?;
 
-    Ok(reading)
+    Ok(pending)
 }
 
-pub async fn register_user(pool: &PgPool, user_form: UserForm) -> Result<(), sqlx::Error> {
-    // Calculate Argon2 password hash
-    let hash = calculate_hash(user_form.password.as_str());
-    // Insert into DB
-    sqlx::query!(
+/// Number of readings `fetch_pending_proofs` would return without a limit
+pub async fn count_pending_proofs(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
         r#"
-        INSERT INTO users (username, password)
-        VALUES ($1, $2)
+        SELECT COUNT(*) as "count!"
+        FROM readings
+        WHERE tx_signature = ''
+        AND proof_cancelled_at IS NULL
+        AND timestamp < $1
         "#,
-        user_form.username,
-        hash
+        cutoff
     )
-    .execute(pool)
+    .fetch_one(pool)
     .await?;
 
-    println!("New user created: {}", user_form.username);
-    Ok(())
+    Ok(count)
 }
 
-pub async fn user_login(pool: &PgPool, user_form: &UserForm) -> Result<bool, sqlx::Error> {
-    // Read stored hash from DB
-    let stored_hash = sqlx::query_as::<_, UserRecord>(
+/// Gives up on the proofs of readings pending since before `cutoff`, dropping their dead
+/// letters so they are no longer retried. Returns the number of readings cancelled.
+pub async fn cancel_pending_proofs(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let cancelled = sqlx::query!(
         r#"
-        SELECT password
-        FROM users
-        WHERE username = $1
+        UPDATE readings
+        SET proof_cancelled_at = NOW()
+        WHERE tx_signature = ''
+        AND proof_cancelled_at IS NULL
+        AND timestamp < $1
         "#,
+        cutoff
     )
-    .bind(&user_form.username)
-    .fetch_optional(pool)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query!(
+        r#"
+        DELETE FROM dead_letters d
+        USING readings r
+        WHERE d.reading_id = r.id
+        AND r.proof_cancelled_at IS NOT NULL
+        "#
+    )
+    .execute(&mut *tx)
     .await?;
 
-    Ok(stored_hash.map_or(false, |r| verify_hash(&user_form.password, &r.0)))
+    tx.commit().await?;
+    Ok(cancelled)
 }
 
-pub async fn sensor_exists(pool: &PgPool, sensor_id: i32) -> Result<bool, sqlx::Error> {
+/// Whether the sensor already has a reading stored at this timestamp
+pub async fn reading_exists(
+    pool: &PgPool,
+    sensor_id: i32,
+    timestamp: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
     let exists = sqlx::query!(
         r#"
-        SELECT EXISTS(SELECT 1 FROM sensors WHERE id = $1) as "exists!"
+        SELECT EXISTS(
+            SELECT 1 FROM readings WHERE sensor_id = $1 AND timestamp = $2
+        ) as "exists!"
         "#,
-        sensor_id
+        sensor_id,
+        timestamp
     )
     .fetch_one(pool)
     .await?
@@ -221,25 +814,1634 @@ pub async fn sensor_exists(pool: &PgPool, sensor_id: i32) -> Result<bool, sqlx::
     Ok(exists)
 }
 
-pub async fn owns_sensor(
+/// Transaction signature of the reading recorded under a sensor's sequence number
+pub async fn recorded_signature(
     pool: &PgPool,
-    username: String,
     sensor_id: i32,
-) -> Result<bool, sqlx::Error> {
-    let result = sqlx::query!(
+    seq: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    let signature = sqlx::query_scalar!(
         r#"
-        SELECT EXISTS(
-            SELECT 1
-            FROM sensors s
-            INNER JOIN users u ON s.user_id = u.id
-            WHERE s.id = $1 AND u.username = $2
-        ) as "exists!"
+        SELECT tx_signature
+        FROM readings
+        WHERE sensor_id = $1
+        AND seq = $2
         "#,
         sensor_id,
-        username
+        seq
     )
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(result.exists)
+    Ok(signature)
+}
+
+/// Inserts readings awaiting their proof atomically, so a chunk is either fully stored or
+/// not at all. Returns each reading's id, `None` for retransmitted sequence numbers.
+pub async fn insert_readings_chunk(
+    pool: &PgPool,
+    chunk: &[SensorReading],
+) -> Result<Vec<Option<i32>>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut ids = Vec::with_capacity(chunk.len());
+    for reading in chunk {
+        ids.push(insert_reading(&mut *tx, reading, String::new()).await?);
+    }
+    tx.commit().await?;
+    Ok(ids)
+}
+
+/// Inserts readings awaiting their proof with a single multi-row INSERT, skipping
+/// retransmitted sequence numbers and duplicate timestamps. Returns each reading's id,
+/// `None` for skipped ones.
+pub async fn insert_readings_batch(
+    pool: &PgPool,
+    batch: &[SensorReading],
+) -> Result<Vec<Option<i32>>, sqlx::Error> {
+    if batch.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut query = QueryBuilder::new(
+        r#"
+        INSERT INTO readings (
+            sensor_id,
+            timestamp,
+            co2_level,
+            temperature,
+            pressure,
+            wind_speed,
+            wind_direction,
+            pm25,
+            pm10,
+            humidity,
+            co2_compensated,
+            tx_signature,
+            seq,
+            backfilled
+        )
+        "#,
+    );
+    query.push_values(batch, |mut row, reading| {
+        row.push_bind(reading.sensor_id)
+            .push_bind(reading.timestamp)
+            .push_bind(reading.co2)
+            .push_bind(reading.temperature)
+            .push_bind(reading.pressure)
+            .push_bind(reading.wind_speed)
+            .push_bind(reading.wind_direction)
+            .push_bind(reading.pm25)
+            .push_bind(reading.pm10)
+            .push_bind(reading.humidity)
+            .push_bind(reading.co2_compensated)
+            .push_bind("")
+            .push_bind(reading.seq)
+            .push_bind(reading.backfilled);
+    });
+    query.push(" ON CONFLICT DO NOTHING RETURNING id, sensor_id, timestamp");
+
+    // A sensor reports a single reading per timestamp, which identifies the stored rows
+    let stored: HashMap<(i32, DateTime<Utc>), i32> = query
+        .build_query_as::<(i32, i32, DateTime<Utc>)>()
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(id, sensor_id, timestamp)| ((sensor_id, timestamp), id))
+        .collect();
+    tracing::info!("Inserted batch of {} readings", stored.len());
+    let ids = batch
+        .iter()
+        .map(|reading| stored.get(&(reading.sensor_id, reading.timestamp)).copied())
+        .collect();
+    Ok(ids)
+}
+
+/// Folds a reading into the sensor's latest stored one when the sensor opted into coalescing,
+/// the values are within `epsilon` and it arrives within `window` of when that reading was
+/// last seen. Returns whether the reading was coalesced (and must not be stored or anchored).
+pub async fn coalesce_reading(
+    pool: &PgPool,
+    payload: &SensorReading,
+    epsilon: f32,
+    window: Duration,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE readings r
+        SET last_seen = $2
+        FROM sensors s
+        WHERE s.id = r.sensor_id
+        AND s.coalesce_readings
+        AND r.id = (
+            SELECT id FROM readings
+            WHERE sensor_id = $1
+            ORDER BY timestamp DESC
+            LIMIT 1
+        )
+        AND $2 > COALESCE(r.last_seen, r.timestamp)
+        AND $2 - COALESCE(r.last_seen, r.timestamp) <= make_interval(secs => $5)
+        AND ABS(r.co2_level - $3) <= $6
+        AND ABS(r.temperature - $4) <= $6
+        AND COALESCE(ABS(r.pressure - $7) <= $6, r.pressure IS NULL AND $7 IS NULL)
+        AND COALESCE(ABS(r.wind_speed - $8) <= $6, r.wind_speed IS NULL AND $8 IS NULL)
+        AND COALESCE(ABS(r.wind_direction - $9) <= $6, r.wind_direction IS NULL AND $9 IS NULL)
+        AND COALESCE(ABS(r.pm25 - $10) <= $6, r.pm25 IS NULL AND $10 IS NULL)
+        AND COALESCE(ABS(r.pm10 - $11) <= $6, r.pm10 IS NULL AND $11 IS NULL)
+        AND COALESCE(ABS(r.humidity - $12) <= $6, r.humidity IS NULL AND $12 IS NULL)
+        "#,
+    )
+    .bind(payload.sensor_id)
+    .bind(payload.timestamp)
+    .bind(payload.co2)
+    .bind(payload.temperature)
+    .bind(window.num_milliseconds() as f64 / 1000.0)
+    .bind(epsilon)
+    .bind(payload.pressure)
+    .bind(payload.wind_speed)
+    .bind(payload.wind_direction)
+    .bind(payload.pm25)
+    .bind(payload.pm10)
+    .bind(payload.humidity)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Earliest timestamp the sensor's next reading may carry, if it has a minimum sampling interval
+pub async fn next_allowed_reading(
+    pool: &PgPool,
+    sensor_id: i32,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let next_allowed = sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"
+        SELECT MAX(r.timestamp) + make_interval(secs => s.min_interval_secs)
+        FROM sensors s
+        INNER JOIN readings r ON r.sensor_id = s.id
+        WHERE s.id = $1
+        AND s.min_interval_secs IS NOT NULL
+        GROUP BY s.min_interval_secs
+        "#,
+    )
+    .bind(sensor_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(next_allowed)
+}
+
+pub async fn fetch_sensors(
+    pool: &PgPool,
+    username: String,
+    limit: i64,
+) -> Result<Vec<Sensor>, sqlx::Error> {
+    // Read from DB
+    let sensors = sqlx::query_as::<_, Sensor>(
+        r#"
+        SELECT
+            s.id,
+            s.name,
+            s.location
+        FROM sensors s
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE u.username = $1
+        ORDER BY s.name ASC
+        LIMIT $2
+        "#,
+    )
+    .bind(username)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(sensors)
+}
+
+/// Every sensor of every user, for administrators
+pub async fn fetch_all_sensors(pool: &PgPool, limit: i64) -> Result<Vec<OwnedSensor>, sqlx::Error> {
+    let sensors = sqlx::query_as::<_, OwnedSensor>(
+        r#"
+        SELECT
+            s.id,
+            s.name,
+            s.location,
+            u.username as owner
+        FROM sensors s
+        INNER JOIN users u ON s.user_id = u.id
+        ORDER BY u.username ASC, s.name ASC
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(sensors)
+}
+
+/// Registers a sensor owned by the user
+pub async fn create_sensor(
+    pool: &PgPool,
+    argon2: &Argon2<'_>,
+    username: String,
+    sensor_form: SensorForm,
+    api_key: &str,
+    signing_secret: &str,
+) -> Result<Sensor, sqlx::Error> {
+    let sensor = sqlx::query_as::<_, Sensor>(
+        r#"
+        INSERT INTO sensors (name, location, user_id, api_key_hash, signing_secret)
+        SELECT $1, $2, u.id, $4, $5
+        FROM users u
+        WHERE u.username = $3
+        RETURNING id, name, location
+        "#,
+    )
+    .bind(&sensor_form.name)
+    .bind(&sensor_form.location)
+    .bind(&username)
+    .bind(calculate_hash(argon2, api_key))
+    .bind(signing_secret)
+    .fetch_one(pool)
+    .await?;
+
+    tracing::info!(username, "New sensor created: {:?}", sensor);
+    Ok(sensor)
+}
+
+/// Updates the provided metadata of a sensor, returning it as updated
+pub async fn update_sensor(
+    pool: &PgPool,
+    sensor_id: i32,
+    patch: SensorPatch,
+) -> Result<Sensor, sqlx::Error> {
+    let sensor = sqlx::query_as::<_, Sensor>(
+        r#"
+        UPDATE sensors
+        SET name = COALESCE($2, name),
+            location = COALESCE($3, location),
+            enabled = COALESCE($4, enabled),
+            forward_url = CASE WHEN $5::TEXT IS NULL THEN forward_url ELSE NULLIF($5, '') END,
+            humidity_compensation = COALESCE($6, humidity_compensation)
+        WHERE id = $1
+        RETURNING id, name, location
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(&patch.name)
+    .bind(&patch.location)
+    .bind(patch.enabled)
+    .bind(&patch.forward_url)
+    .bind(patch.humidity_compensation)
+    .fetch_one(pool)
+    .await?;
+
+    tracing::info!(sensor_id, "Sensor updated: {:?}", sensor);
+    Ok(sensor)
+}
+
+/// Sensors among `ids` owned by the user, others are silently skipped
+pub async fn fetch_sensors_by_ids(
+    pool: &PgPool,
+    username: String,
+    ids: &[i32],
+) -> Result<Vec<Sensor>, sqlx::Error> {
+    // Read from DB
+    let sensors = sqlx::query_as::<_, Sensor>(
+        r#"
+        SELECT
+            s.id,
+            s.name,
+            s.location
+        FROM sensors s
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE u.username = $1
+        AND s.id = ANY($2)
+        ORDER BY s.name ASC
+        "#,
+    )
+    .bind(username)
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(sensors)
+}
+
+/// Lists the user's sensors with their latest reading time (coalesced repeats included),
+/// sorted by name or least recently active first
+pub async fn fetch_sensors_activity(
+    pool: &PgPool,
+    username: String,
+    stale_first: bool,
+    limit: i64,
+) -> Result<Vec<SensorActivity>, sqlx::Error> {
+    // Read from DB
+    let sensors = sqlx::query_as::<_, SensorActivity>(
+        r#"
+        SELECT
+            s.id,
+            s.name,
+            s.location,
+            lr.last_reading_at
+        FROM sensors s
+        INNER JOIN users u ON s.user_id = u.id
+        LEFT JOIN LATERAL (
+            SELECT COALESCE(r.last_seen, r.timestamp) as last_reading_at
+            FROM readings r
+            WHERE r.sensor_id = s.id
+            ORDER BY r.timestamp DESC
+            LIMIT 1
+        ) lr ON TRUE
+        WHERE u.username = $1
+        ORDER BY
+            CASE WHEN $2 THEN lr.last_reading_at END ASC NULLS FIRST,
+            s.name ASC
+        LIMIT $3
+        "#,
+    )
+    .bind(username)
+    .bind(stale_first)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(sensors)
+}
+
+/// Readings of the sensor in the time range, restricted to `owner`'s sensors unless `None`
+pub async fn fetch_readings(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    owner: Option<String>,
+    proven: Option<bool>,
+    limit: i64,
+) -> Result<Vec<SensorReadingRecord>, sqlx::Error> {
+    // Read from DB
+    let readings = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            r.id,
+            r.sensor_id,
+            r.timestamp,
+            r.co2_level as co2,
+            r.temperature,
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature
+        FROM readings r
+        INNER JOIN sensors s ON r.sensor_id = s.id
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE r.sensor_id = $1
+        AND ($2::TEXT IS NULL OR u.username = $2)
+        AND r.timestamp BETWEEN $3 AND $4
+        AND ($5::BOOLEAN IS NULL OR (r.tx_signature <> '') = $5)
+        ORDER BY r.timestamp ASC
+        LIMIT $6
+        "#,
+        sensor_id,
+        owner,
+        from,
+        to,
+        proven,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(readings)
+}
+
+/// Readings of several sensors between `from` and `to`, grouped by sensor and oldest first
+pub async fn fetch_readings_multi(
+    pool: &PgPool,
+    sensor_ids: &[i32],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    owner: Option<String>,
+    limit: i64,
+) -> Result<Vec<SensorReadingRecord>, sqlx::Error> {
+    // Read from DB
+    let readings = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            r.id,
+            r.sensor_id,
+            r.timestamp,
+            r.co2_level as co2,
+            r.temperature,
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature
+        FROM readings r
+        INNER JOIN sensors s ON r.sensor_id = s.id
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE r.sensor_id = ANY($1)
+        AND ($2::TEXT IS NULL OR u.username = $2)
+        AND r.timestamp BETWEEN $3 AND $4
+        ORDER BY r.sensor_id ASC, r.timestamp ASC
+        LIMIT $5
+        "#,
+        sensor_ids,
+        owner,
+        from,
+        to,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(readings)
+}
+
+/// Counts the sensor's readings between `from` and `to`, so clients can size their queries
+pub async fn count_readings(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    owner: Option<String>,
+) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM readings r
+        INNER JOIN sensors s ON r.sensor_id = s.id
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE r.sensor_id = $1
+        AND ($2::TEXT IS NULL OR u.username = $2)
+        AND r.timestamp BETWEEN $3 AND $4
+        "#,
+        sensor_id,
+        owner,
+        from,
+        to
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// Planner estimate of the sensor's readings between `from` and `to`, taken from table
+/// statistics without scanning the rows, so it stays fast on huge histories
+pub async fn estimate_readings(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    // First line of the plan, e.g. "Index Only Scan using ... (cost=0.16..8.18 rows=1 width=4)"
+    let plan = sqlx::query_scalar::<_, String>(
+        r#"
+        EXPLAIN
+        SELECT 1
+        FROM readings
+        WHERE sensor_id = $1
+        AND timestamp BETWEEN $2 AND $3
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await?;
+
+    plan.split_once(" rows=")
+        .and_then(|(_, rest)| rest.split(' ').next()?.parse().ok())
+        .ok_or_else(|| sqlx::Error::Protocol(format!("Unexpected query plan: {}", plan)))
+}
+
+/// Streams the sensor's readings between `from` and `to`, oldest first, for exports of
+/// unbounded size
+pub fn stream_readings(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> impl Stream<Item = Result<SensorReadingRecord, sqlx::Error>> + Send + '_ {
+    sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            r.id,
+            r.sensor_id,
+            r.timestamp,
+            r.co2_level as co2,
+            r.temperature,
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature
+        FROM readings r
+        WHERE r.sensor_id = $1
+        AND r.timestamp BETWEEN $2 AND $3
+        ORDER BY r.timestamp ASC
+        "#,
+        sensor_id,
+        from,
+        to
+    )
+    .fetch(pool)
+}
+
+/// Most recent readings of a sensor, newest first
+pub async fn fetch_recent_readings(
+    pool: &PgPool,
+    sensor_id: i32,
+    count: i64,
+) -> Result<Vec<SensorReadingRecord>, sqlx::Error> {
+    let readings = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            r.id,
+            r.sensor_id,
+            r.timestamp,
+            r.co2_level as co2,
+            r.temperature,
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature
+        FROM readings r
+        WHERE r.sensor_id = $1
+        ORDER BY r.timestamp DESC
+        LIMIT $2
+        "#,
+        sensor_id,
+        count
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(readings)
+}
+
+/// Averages, minimums and maximums of the sensor's readings per `bucket` (a `date_trunc`
+/// field such as "hour"), truncated in UTC
+pub async fn aggregate_readings(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    bucket: &str,
+    limit: i64,
+) -> Result<Vec<ReadingAggregate>, sqlx::Error> {
+    let aggregates = sqlx::query_as::<_, ReadingAggregate>(
+        r#"
+        SELECT
+            date_trunc($4, r.timestamp, 'UTC') as bucket,
+            COUNT(*) as readings,
+            AVG(r.co2_level) as co2_avg,
+            MIN(r.co2_level) as co2_min,
+            MAX(r.co2_level) as co2_max,
+            AVG(r.temperature) as temperature_avg,
+            MIN(r.temperature) as temperature_min,
+            MAX(r.temperature) as temperature_max
+        FROM readings r
+        WHERE r.sensor_id = $1
+        AND r.timestamp BETWEEN $2 AND $3
+        GROUP BY 1
+        ORDER BY 1 ASC
+        LIMIT $5
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(from)
+    .bind(to)
+    .bind(bucket)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(aggregates)
+}
+
+/// Finds intervals between consecutive readings longer than the expected sampling period
+pub async fn fetch_gaps(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    expected_interval: Duration,
+    limit: i64,
+) -> Result<Vec<ReadingGap>, sqlx::Error> {
+    // Read from DB
+    let gaps = sqlx::query_as::<_, ReadingGap>(
+        r#"
+        SELECT
+            g.start,
+            g.end,
+            EXTRACT(EPOCH FROM g.end - g.start)::BIGINT as duration_secs
+        FROM (
+            SELECT
+                LAG(r.timestamp) OVER (ORDER BY r.timestamp) as start,
+                r.timestamp as end
+            FROM readings r
+            WHERE r.sensor_id = $1
+            AND r.timestamp BETWEEN $2 AND $3
+        ) g
+        WHERE g.end - g.start > make_interval(secs => $4)
+        ORDER BY g.start ASC
+        LIMIT $5
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(from)
+    .bind(to)
+    .bind(expected_interval.num_seconds() as f64)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(gaps)
+}
+
+/// Finds jumps between consecutive sequence numbers, in arrival order so that
+/// counter resets after a device reboot are not reported as losses
+pub async fn fetch_missing_sequences(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<SequenceGap>, sqlx::Error> {
+    // Read from DB
+    let gaps = sqlx::query_as::<_, SequenceGap>(
+        r#"
+        SELECT
+            g.prev + 1 as first_missing,
+            g.seq - 1 as last_missing,
+            g.seq - g.prev - 1 as missing
+        FROM (
+            SELECT
+                LAG(r.seq) OVER (ORDER BY r.timestamp) as prev,
+                r.seq,
+                r.timestamp
+            FROM readings r
+            WHERE r.sensor_id = $1
+            AND r.timestamp BETWEEN $2 AND $3
+            AND r.seq IS NOT NULL
+        ) g
+        WHERE g.seq - g.prev > 1
+        ORDER BY g.timestamp ASC
+        LIMIT $4
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(from)
+    .bind(to)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(gaps)
+}
+
+/// Aggregates the sensor's readings in the time range for health scoring
+pub async fn reading_stats(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<ReadingStats, sqlx::Error> {
+    // Read from DB
+    let stats = sqlx::query_as::<_, ReadingStats>(
+        r#"
+        WITH w AS (
+            SELECT
+                r.timestamp,
+                r.last_seen,
+                r.co2_level,
+                r.temperature,
+                r.tx_signature,
+                r.seq,
+                LAG(r.seq) OVER (PARTITION BY r.seq IS NULL ORDER BY r.timestamp) as prev_seq,
+                AVG(r.co2_level) OVER () as co2_avg,
+                STDDEV_POP(r.co2_level) OVER () as co2_sd,
+                AVG(r.temperature) OVER () as temperature_avg,
+                STDDEV_POP(r.temperature) OVER () as temperature_sd
+            FROM readings r
+            WHERE r.sensor_id = $1
+            AND r.timestamp BETWEEN $2 AND $3
+        )
+        SELECT
+            COUNT(*) as readings,
+            COUNT(*) FILTER (WHERE tx_signature <> '') as proven,
+            COUNT(*) FILTER (
+                WHERE ABS(co2_level - co2_avg) > 3 * co2_sd
+                OR ABS(temperature - temperature_avg) > 3 * temperature_sd
+            ) as outliers,
+            COUNT(seq) as sequenced,
+            COALESCE(SUM(seq - prev_seq - 1) FILTER (WHERE seq - prev_seq > 1), 0)::BIGINT
+                as missing_sequences,
+            MAX(COALESCE(last_seen, timestamp)) as last_reading_at
+        FROM w
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn particulate_averages(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<ParticulateAverages, sqlx::Error> {
+    // Read from DB
+    let averages = sqlx::query_as::<_, ParticulateAverages>(
+        r#"
+        SELECT
+            AVG(r.pm25)::DOUBLE PRECISION as pm25,
+            AVG(r.pm10)::DOUBLE PRECISION as pm10
+        FROM readings r
+        WHERE r.sensor_id = $1
+        AND r.timestamp BETWEEN $2 AND $3
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(averages)
+}
+
+pub async fn fetch_reading(
+    pool: &PgPool,
+    reading_id: i32,
+    username: String,
+) -> Result<SensorReadingRecord, sqlx::Error> {
+    let reading = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            r.id,
+            r.sensor_id,
+            r.timestamp,
+            r.co2_level as co2,
+            r.temperature,
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature
+        FROM readings r
+        INNER JOIN sensors s ON r.sensor_id = s.id
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE r.id = $1
+        AND u.username = $2
+        "#,
+        reading_id,
+        username
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(reading)
+}
+
+/// A single reading of the sensor, `None` when the id doesn't exist or belongs to another sensor
+pub async fn fetch_reading_by_id(
+    pool: &PgPool,
+    sensor_id: i32,
+    reading_id: i32,
+) -> Result<Option<SensorReadingRecord>, sqlx::Error> {
+    let reading = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            id,
+            sensor_id,
+            timestamp,
+            co2_level as co2,
+            temperature,
+            pressure,
+            wind_speed,
+            wind_direction,
+            pm25,
+            pm10,
+            humidity,
+            co2_compensated,
+            backfilled,
+            tx_signature
+        FROM readings
+        WHERE id = $1
+        AND sensor_id = $2
+        "#,
+        reading_id,
+        sensor_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(reading)
+}
+
+/// The sensor's most recent reading, if it has any
+pub async fn fetch_latest_reading(
+    pool: &PgPool,
+    sensor_id: i32,
+) -> Result<Option<SensorReadingRecord>, sqlx::Error> {
+    let reading = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT
+            id,
+            sensor_id,
+            timestamp,
+            co2_level as co2,
+            temperature,
+            pressure,
+            wind_speed,
+            wind_direction,
+            pm25,
+            pm10,
+            humidity,
+            co2_compensated,
+            backfilled,
+            tx_signature
+        FROM readings
+        WHERE sensor_id = $1
+        ORDER BY timestamp DESC
+        LIMIT 1
+        "#,
+        sensor_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(reading)
+}
+
+/// The most recent reading of each of the user's sensors, sensors without readings omitted
+pub async fn fetch_latest_readings(
+    pool: &PgPool,
+    username: String,
+    limit: i64,
+) -> Result<Vec<SensorReadingRecord>, sqlx::Error> {
+    let readings = sqlx::query_as!(
+        SensorReadingRecord,
+        r#"
+        SELECT DISTINCT ON (r.sensor_id)
+            r.id,
+            r.sensor_id,
+            r.timestamp,
+            r.co2_level as co2,
+            r.temperature,
+            r.pressure,
+            r.wind_speed,
+            r.wind_direction,
+            r.pm25,
+            r.pm10,
+            r.humidity,
+            r.co2_compensated,
+            r.backfilled,
+            r.tx_signature
+        FROM readings r
+        INNER JOIN sensors s ON r.sensor_id = s.id
+        INNER JOIN users u ON s.user_id = u.id
+        WHERE u.username = $1
+        ORDER BY r.sensor_id ASC, r.timestamp DESC
+        LIMIT $2
+        "#,
+        username,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(readings)
+}
+
+/// Records that a stored reading crossed its sensor's alert threshold
+pub async fn record_alert(
+    pool: &PgPool,
+    sensor_id: i32,
+    reading_id: i32,
+    co2: f32,
+    threshold: f32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO alerts (sensor_id, reading_id, co2_level, threshold)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        sensor_id,
+        reading_id,
+        co2,
+        threshold
+    )
+    .execute(pool)
+    .await?;
+
+    tracing::warn!(
+        sensor_id,
+        reading_id,
+        co2,
+        threshold,
+        "CO2 alert threshold exceeded"
+    );
+    Ok(())
+}
+
+/// Alerts raised in the time range, newest first
+pub async fn fetch_alerts(
+    pool: &PgPool,
+    sensor_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<Alert>, sqlx::Error> {
+    let alerts = sqlx::query_as!(
+        Alert,
+        r#"
+        SELECT
+            id,
+            reading_id,
+            co2_level as co2,
+            threshold,
+            created_at
+        FROM alerts
+        WHERE sensor_id = $1
+        AND created_at BETWEEN $2 AND $3
+        ORDER BY created_at DESC
+        LIMIT $4
+        "#,
+        sensor_id,
+        from,
+        to,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(alerts)
+}
+
+pub async fn register_user(
+    pool: &PgPool,
+    argon2: &Argon2<'_>,
+    user_form: UserForm,
+) -> Result<(), sqlx::Error> {
+    // Calculate Argon2 password hash
+    let hash = calculate_hash(argon2, user_form.password.as_str());
+    // Insert into DB
+    sqlx::query!(
+        r#"
+        INSERT INTO users (username, password)
+        VALUES ($1, $2)
+        "#,
+        user_form.username,
+        hash
+    )
+    .execute(pool)
+    .await?;
+
+    tracing::info!(username = %user_form.username, "New user created");
+    Ok(())
+}
+
+/// Returns the user's token identity (version, role) when the credentials are valid
+pub async fn user_login(
+    pool: &PgPool,
+    argon2: &Argon2<'_>,
+    user_form: &UserForm,
+) -> Result<Option<TokenOwner>, sqlx::Error> {
+    // Read stored hash from DB
+    let stored_hash = sqlx::query_as::<_, UserRecord>(
+        r#"
+        SELECT username, password, token_version, role
+        FROM users
+        WHERE username = $1
+        "#,
+    )
+    .bind(&user_form.username)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(stored_hash
+        .filter(|r| verify_hash(argon2, &user_form.password, &r.password))
+        .map(|r| r.owner))
+}
+
+/// Replaces the user's password hash
+pub async fn update_password(
+    pool: &PgPool,
+    argon2: &Argon2<'_>,
+    username: &str,
+    password: &str,
+) -> Result<(), sqlx::Error> {
+    // Calculate Argon2 password hash
+    let hash = calculate_hash(argon2, password);
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET password = $2
+        WHERE username = $1
+        "#,
+    )
+    .bind(username)
+    .bind(hash)
+    .execute(pool)
+    .await?;
+
+    tracing::info!(username, "Password changed");
+    Ok(())
+}
+
+/// Sets the user's default `forward_url`, `None` to clear it
+pub async fn set_user_forward_url(
+    pool: &PgPool,
+    username: &str,
+    forward_url: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET forward_url = $2
+        WHERE username = $1
+        "#,
+    )
+    .bind(username)
+    .bind(forward_url)
+    .execute(pool)
+    .await?;
+
+    tracing::info!(username, "Forwarding target changed");
+    Ok(())
+}
+
+pub async fn token_version(pool: &PgPool, username: &str) -> Result<Option<i32>, sqlx::Error> {
+    let version = sqlx::query_scalar::<_, i32>(
+        r#"
+        SELECT token_version
+        FROM users
+        WHERE username = $1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(version)
+}
+
+/// Invalidates every token issued to the user so far, refresh tokens included
+pub async fn bump_token_version(pool: &PgPool, username: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET token_version = token_version + 1
+        WHERE username = $1
+        "#,
+    )
+    .bind(username)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE user_id = (SELECT id FROM users WHERE username = $1)
+        "#,
+    )
+    .bind(username)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    tracing::info!(username, "Revoked all tokens of user");
+    Ok(())
+}
+
+/// Stores the digest of a refresh token issued to the user, purging their expired ones
+pub async fn create_refresh_token(
+    pool: &PgPool,
+    username: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE user_id = (SELECT id FROM users WHERE username = $1)
+        AND expires_at <= NOW()
+        "#,
+    )
+    .bind(username)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (user_id, token_hash, expires_at)
+        SELECT id, $2, $3 FROM users WHERE username = $1
+        "#,
+    )
+    .bind(username)
+    .bind(token_hash)
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// User a refresh token was issued to, `None` if it is unknown, revoked or expired
+pub async fn refresh_token_owner(
+    pool: &PgPool,
+    token_hash: &str,
+) -> Result<Option<TokenOwner>, sqlx::Error> {
+    let owner = sqlx::query_as::<_, TokenOwner>(
+        r#"
+        SELECT u.username, u.token_version, u.role
+        FROM refresh_tokens t
+        INNER JOIN users u ON t.user_id = u.id
+        WHERE t.token_hash = $1
+        AND t.expires_at > NOW()
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(owner)
+}
+
+/// Revokes a refresh token, returns whether it existed
+pub async fn revoke_refresh_token(pool: &PgPool, token_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE token_hash = $1
+        "#,
+    )
+    .bind(token_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revokes an access token until its original expiry, purging entries already expired
+pub async fn revoke_access_token(
+    pool: &PgPool,
+    jti: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        DELETE FROM revoked_tokens
+        WHERE expires_at <= NOW()
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_tokens (jti, expires_at)
+        VALUES ($1, $2)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(jti)
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Whether an access token was revoked before its expiry
+pub async fn token_revoked(pool: &PgPool, jti: &str) -> Result<bool, sqlx::Error> {
+    let revoked = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)
+        "#,
+    )
+    .bind(jti)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(revoked)
+}
+
+/// Loads the sensor's ingest settings, `None` if the sensor is not registered
+pub async fn ingest_settings(
+    pool: &PgPool,
+    sensor_id: i32,
+) -> Result<Option<IngestSettings>, sqlx::Error> {
+    let settings = sqlx::query_as::<_, IngestSettings>(
+        r#"
+        SELECT
+            s.enabled,
+            s.sensor_type,
+            s.past_reading_policy,
+            s.strict_ordering,
+            CASE WHEN s.strict_ordering THEN (
+                SELECT MAX(r.timestamp) FROM readings r WHERE r.sensor_id = s.id
+            ) END as latest_reading,
+            s.co2_min,
+            s.co2_max,
+            s.temperature_min,
+            s.temperature_max,
+            s.name,
+            s.location,
+            COALESCE(s.forward_url, u.forward_url) as forward_url,
+            s.alert_threshold,
+            s.humidity_compensation
+        FROM sensors s
+        LEFT JOIN users u ON s.user_id = u.id
+        WHERE s.id = $1
+        "#,
+    )
+    .bind(sensor_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(settings)
+}
+
+pub async fn fetch_sensor(pool: &PgPool, sensor_id: i32) -> Result<SensorDetail, sqlx::Error> {
+    let sensor = sqlx::query_as::<_, SensorDetail>(
+        r#"
+        SELECT
+            id,
+            name,
+            location,
+            enabled,
+            co2_min,
+            co2_max,
+            temperature_min,
+            temperature_max
+        FROM sensors
+        WHERE id = $1
+        "#,
+    )
+    .bind(sensor_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(sensor)
+}
+
+/// Secrets authenticating a sensor's ingest requests
+#[derive(Debug, FromRow)]
+pub struct SensorCredentials {
+    pub(crate) api_key_hash: Option<String>, // No key refuses ingest
+    pub(crate) signing_secret: Option<String>,
+    pub(crate) require_signature: bool,
+}
+
+pub async fn sensor_credentials(
+    pool: &PgPool,
+    sensor_id: i32,
+) -> Result<Option<SensorCredentials>, sqlx::Error> {
+    let credentials = sqlx::query_as::<_, SensorCredentials>(
+        r#"
+        SELECT api_key_hash, signing_secret, require_signature
+        FROM sensors
+        WHERE id = $1
+        "#,
+    )
+    .bind(sensor_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(credentials)
+}
+
+pub async fn sensor_exists(pool: &PgPool, sensor_id: i32) -> Result<bool, sqlx::Error> {
+    let exists = sqlx::query!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM sensors WHERE id = $1) as "exists!"
+        "#,
+        sensor_id
+    )
+    .fetch_one(pool)
+    .await?
+    .exists;
+
+    Ok(exists)
+}
+
+/// Whether the user owns the sensor, `None` when the sensor doesn't exist
+pub async fn sensor_access(
+    executor: impl PgExecutor<'_>,
+    username: String,
+    sensor_id: i32,
+) -> Result<Option<bool>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT COALESCE(u.username = $2, FALSE) as "owned!"
+        FROM sensors s
+        LEFT JOIN users u ON s.user_id = u.id
+        WHERE s.id = $1
+        "#,
+        sensor_id,
+        username
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(result.map(|r| r.owned))
+}
+
+/// Deletes a sensor along with its readings when `force` is set. Returns the number of
+/// readings deleted, or `None` when the sensor has readings and `force` isn't set.
+pub async fn delete_sensor(
+    pool: &PgPool,
+    sensor_id: i32,
+    force: bool,
+) -> Result<Option<u64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let has_readings = sqlx::query!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM readings WHERE sensor_id = $1) as "exists!"
+        "#,
+        sensor_id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .exists;
+    if has_readings && !force {
+        return Ok(None);
+    }
+
+    let readings = sqlx::query!("DELETE FROM readings WHERE sensor_id = $1", sensor_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    sqlx::query!("DELETE FROM sensors WHERE id = $1", sensor_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    tracing::info!(sensor_id, readings, "Deleted sensor");
+    Ok(Some(readings))
+}
+
+/// Deletes the user with their sensors and readings, returning how many of each were removed.
+/// Readings go first since they don't cascade, alerts and refresh tokens follow on their own.
+pub async fn delete_user(pool: &PgPool, username: &str) -> Result<(u64, u64), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let readings = sqlx::query!(
+        r#"
+        DELETE FROM readings
+        WHERE sensor_id IN (
+            SELECT s.id
+            FROM sensors s
+            INNER JOIN users u ON s.user_id = u.id
+            WHERE u.username = $1
+        )
+        "#,
+        username
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let sensors = sqlx::query!(
+        r#"
+        DELETE FROM sensors
+        WHERE user_id = (SELECT id FROM users WHERE username = $1)
+        "#,
+        username
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query!("DELETE FROM users WHERE username = $1", username)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    tracing::info!(username, sensors, readings, "Deleted user");
+    Ok((sensors, readings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_accepted_when_all_rules_hold() {
+        assert!(validate_password("alice", "s3cretpass").is_ok());
+        assert!(validate_password("alice", "passw0rd").is_ok());
+    }
+
+    #[test]
+    fn password_rejected_below_minimum_length() {
+        assert!(matches!(
+            validate_password("alice", "pa55wrd"),
+            Err(ApiError::PasswordTooShort { min_len: 8 })
+        ));
+        // Length counts characters, not bytes
+        assert!(validate_password("alice", "ñññññññ1").is_ok());
+    }
+
+    #[test]
+    fn password_rejected_without_letter_or_digit() {
+        for password in ["passwordonly", "1234567890", "!@#$%^&*()"] {
+            assert!(matches!(
+                validate_password("alice", password),
+                Err(ApiError::PasswordTooSimple)
+            ));
+        }
+    }
+
+    #[test]
+    fn password_rejected_when_equal_to_username() {
+        assert!(matches!(
+            validate_password("alice2024", "alice2024"),
+            Err(ApiError::PasswordIsUsername)
+        ));
+        // Only an exact match is refused
+        assert!(validate_password("alice2024", "alice2024x").is_ok());
+    }
+
+    fn limits() -> TimestampLimits {
+        TimestampLimits {
+            max_age: Duration::days(180),
+            max_skew: Duration::minutes(5),
+        }
+    }
+
+    #[test]
+    fn timestamp_too_old_boundary() {
+        let now = Utc::now();
+        let oldest = now - Duration::days(180);
+        assert!(check_timestamp(oldest, &limits(), now).is_ok());
+        assert!(check_timestamp(oldest + Duration::seconds(1), &limits(), now).is_ok());
+        assert!(matches!(
+            check_timestamp(oldest - Duration::seconds(1), &limits(), now),
+            Err(ApiError::TimestampTooOld { max_age_days: 180 })
+        ));
+    }
+
+    #[test]
+    fn timestamp_too_new_boundary() {
+        let now = Utc::now();
+        let newest = now + Duration::minutes(5);
+        assert!(check_timestamp(newest, &limits(), now).is_ok());
+        assert!(check_timestamp(newest - Duration::seconds(1), &limits(), now).is_ok());
+        assert!(matches!(
+            check_timestamp(newest + Duration::seconds(1), &limits(), now),
+            Err(ApiError::TimestampInFuture)
+        ));
+    }
+
+    /// Needs the database the `query!` macros are checked against. Everything is written
+    /// in a transaction which is rolled back.
+    #[tokio::test]
+    async fn unknown_sensor_told_apart_from_foreign_one() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        let mut tx = pool.begin().await.unwrap();
+
+        let user_id = sqlx::query_scalar!(
+            "INSERT INTO users (username, password) VALUES ('access-owner', 'x') RETURNING id"
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        let sensor_id = sqlx::query_scalar!(
+            "INSERT INTO sensors (name, location, user_id) VALUES ('access', 'test', $1) RETURNING id",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+
+        let owned = sensor_access(&mut *tx, "access-owner".into(), sensor_id).await;
+        let foreign = sensor_access(&mut *tx, "access-other".into(), sensor_id).await;
+        let unknown = sensor_access(&mut *tx, "access-owner".into(), i32::MAX).await;
+        tx.rollback().await.unwrap();
+
+        assert_eq!(owned.unwrap(), Some(true));
+        assert_eq!(foreign.unwrap(), Some(false));
+        assert_eq!(unknown.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn readings_estimated_from_query_plan() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(&url).await.unwrap();
+
+        let to = Utc::now();
+        let estimate = estimate_readings(&pool, i32::MAX, to - Duration::days(7), to)
+            .await
+            .unwrap();
+        assert!(estimate >= 0);
+    }
+
+    #[tokio::test]
+    async fn reading_round_trips_through_real_columns() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        let mut tx = pool.begin().await.unwrap();
+
+        let sensor_id = sqlx::query_scalar!(
+            "INSERT INTO sensors (name, location) VALUES ('round-trip', 'test') RETURNING id"
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        // Fractional values, which must come back bit for bit
+        let reading = SensorReading {
+            sensor_id,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            co2: 412.7,
+            temperature: -3.1,
+            pressure: Some(1013.25),
+            wind_speed: None,
+            wind_direction: Some(359.9),
+            pm25: Some(0.1),
+            pm10: None,
+            humidity: Some(55.5),
+            co2_compensated: Some(418.9),
+            seq: None,
+            nonce: None,
+            backfilled: false,
+        };
+        let id = insert_reading(&mut *tx, &reading, "sig".into())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let record = sqlx::query_as!(
+            SensorReadingRecord,
+            r#"
+            SELECT
+                id,
+                sensor_id,
+                timestamp,
+                co2_level as co2,
+                temperature,
+                pressure,
+                wind_speed,
+                wind_direction,
+                pm25,
+                pm10,
+                humidity,
+                co2_compensated,
+                backfilled,
+                tx_signature
+            FROM readings
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        tx.rollback().await.unwrap();
+
+        assert_eq!(record.tx_signature, "sig");
+        let stored = SensorReading::from(record);
+        assert_eq!(stored.timestamp, reading.timestamp);
+        assert_eq!(stored.co2.to_bits(), reading.co2.to_bits());
+        assert_eq!(stored.temperature.to_bits(), reading.temperature.to_bits());
+        assert_eq!(stored.pressure, reading.pressure);
+        assert_eq!(stored.wind_speed, None);
+        assert_eq!(stored.wind_direction, reading.wind_direction);
+        assert_eq!(stored.pm25, reading.pm25);
+        assert_eq!(stored.pm10, None);
+        assert_eq!(stored.humidity, reading.humidity);
+        assert_eq!(stored.co2_compensated, reading.co2_compensated);
+    }
 }
@@ -0,0 +1,419 @@
+use crate::http::HttpResponse;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+use std::fmt;
+use std::time::Duration;
+
+/// Failures reported to API clients, each with its HTTP status and a stable code that
+/// clients can match on regardless of the message wording
+#[derive(Debug)]
+pub enum ApiError {
+    InvalidPayload {
+        reason: String,
+    }, // Undecodable body, with the decoder's explanation
+    TestModeDisabled,
+    TooManyReadings {
+        what: &'static str,
+        max: usize,
+    },
+    StreamTooLarge {
+        limit: usize,
+    },
+    RejectedLine {
+        line: usize,
+        reason: String,
+    }, // Ends an atomic stream
+    InvalidReadings {
+        count: usize,
+    }, // Refuses a whole batch
+    MissingApiKey,
+    InvalidApiKey,
+    MissingSignature,
+    InvalidSignature,
+    SignedFramesUnsupported,
+    SignedStreamNotAtomic,
+    MixedSignedStream,
+    ForeignReading, // Sent for a sensor other than the authenticated one
+    InvalidNonce,
+    SensorDisabled,
+    SensorTypeBlocked,
+    OutOfOrder {
+        last_accepted: DateTime<Utc>,
+    },
+    DuplicateReading,
+    RateLimited {
+        retry_after: Duration,
+    },
+    TooSoon {
+        next_allowed: DateTime<Utc>,
+    }, // Before the sensor's minimum sampling interval
+    InvalidSeq,
+    InvalidValue {
+        field: &'static str,
+    }, // NaN
+    BelowMinimum {
+        field: &'static str,
+        min: f32,
+    },
+    AboveMaximum {
+        field: &'static str,
+        max: f32,
+    },
+    StaleReading, // Refused by the past-dated reading policy
+    TimestampTooOld {
+        max_age_days: i64,
+    },
+    TimestampInFuture,
+    InvalidSensorField {
+        field: &'static str,
+    },
+    NothingToUpdate,
+    InvalidForwardUrl {
+        reason: &'static str,
+    },
+    InvalidTimeRange {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    }, // Inverted bounds
+    UnboundedRange, // range=all, on an endpoint returning raw readings
+    InvalidIds,
+    NoSensorIds,
+    TooManyIds {
+        field: &'static str,
+        max: usize,
+    },
+    InvalidInterval,
+    PasswordTooShort {
+        min_len: usize,
+    },
+    PasswordTooSimple, // Lacks a letter or a digit
+    PasswordIsUsername,
+    InvalidCredentials,
+    WrongPassword, // Confirmation of the signed-in user's password
+    InvalidRefreshToken,
+    UsernameTaken,
+    SensorNotFound,
+    ReadingNotFound,
+    NoParticulateReadings, // Nothing to compute an AQI from
+    NotOwner,
+    SensorHasReadings,
+    AdminRequired,
+    DeadLetterNotFound,
+    AlreadyAnchored, // The reading's proof is recorded already
+    TooManyRows {
+        what: &'static str,
+    }, // Over the response row ceiling
+    WalletDepleted,
+    ProofQueueFull,
+    DatabaseTimeout, // statement_timeout cancelled the query
+    Internal,        // Details are logged, never sent to clients
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidPayload { .. }
+            | Self::TestModeDisabled
+            | Self::TooManyReadings { .. }
+            | Self::RejectedLine { .. }
+            | Self::InvalidReadings { .. }
+            | Self::SignedStreamNotAtomic
+            | Self::MixedSignedStream
+            | Self::InvalidNonce
+            | Self::InvalidSeq
+            | Self::InvalidValue { .. }
+            | Self::BelowMinimum { .. }
+            | Self::AboveMaximum { .. }
+            | Self::StaleReading
+            | Self::TimestampTooOld { .. }
+            | Self::TimestampInFuture
+            | Self::InvalidSensorField { .. }
+            | Self::NothingToUpdate
+            | Self::InvalidForwardUrl { .. }
+            | Self::InvalidTimeRange { .. }
+            | Self::UnboundedRange
+            | Self::InvalidIds
+            | Self::NoSensorIds
+            | Self::TooManyIds { .. }
+            | Self::InvalidInterval
+            | Self::PasswordTooShort { .. }
+            | Self::PasswordTooSimple
+            | Self::PasswordIsUsername
+            | Self::TooManyRows { .. } => StatusCode::BAD_REQUEST,
+            Self::MissingApiKey
+            | Self::InvalidApiKey
+            | Self::MissingSignature
+            | Self::InvalidSignature
+            | Self::InvalidCredentials
+            | Self::WrongPassword
+            | Self::InvalidRefreshToken => StatusCode::UNAUTHORIZED,
+            Self::SensorNotFound
+            | Self::ReadingNotFound
+            | Self::NoParticulateReadings
+            | Self::DeadLetterNotFound => StatusCode::NOT_FOUND,
+            Self::SignedFramesUnsupported
+            | Self::ForeignReading
+            | Self::SensorDisabled
+            | Self::SensorTypeBlocked
+            | Self::NotOwner
+            | Self::AdminRequired => StatusCode::FORBIDDEN,
+            Self::OutOfOrder { .. }
+            | Self::DuplicateReading
+            | Self::SensorHasReadings
+            | Self::AlreadyAnchored
+            | Self::UsernameTaken => StatusCode::CONFLICT,
+            Self::StreamTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RateLimited { .. } | Self::TooSoon { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::WalletDepleted | Self::ProofQueueFull | Self::DatabaseTimeout => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidPayload { .. } => "invalid_payload",
+            Self::TestModeDisabled => "test_mode_disabled",
+            Self::TooManyReadings { .. } => "too_many_readings",
+            Self::StreamTooLarge { .. } => "stream_too_large",
+            Self::RejectedLine { .. } => "rejected_line",
+            Self::InvalidReadings { .. } => "invalid_readings",
+            Self::MissingApiKey => "missing_api_key",
+            Self::InvalidApiKey => "invalid_api_key",
+            Self::MissingSignature => "missing_signature",
+            Self::InvalidSignature => "invalid_signature",
+            Self::SignedFramesUnsupported => "signed_frames_unsupported",
+            Self::SignedStreamNotAtomic => "signed_stream_not_atomic",
+            Self::MixedSignedStream => "mixed_signed_stream",
+            Self::ForeignReading => "foreign_reading",
+            Self::InvalidNonce => "invalid_nonce",
+            Self::SensorDisabled => "sensor_disabled",
+            Self::SensorTypeBlocked => "sensor_type_blocked",
+            Self::OutOfOrder { .. } => "out_of_order",
+            Self::DuplicateReading => "duplicate_reading",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::TooSoon { .. } => "too_soon",
+            Self::InvalidSeq => "invalid_seq",
+            Self::InvalidValue { .. } => "invalid_value",
+            Self::BelowMinimum { .. } => "below_minimum",
+            Self::AboveMaximum { .. } => "above_maximum",
+            Self::StaleReading => "stale_reading",
+            Self::TimestampTooOld { .. } => "timestamp_too_old",
+            Self::TimestampInFuture => "timestamp_in_future",
+            Self::InvalidSensorField { .. } => "invalid_sensor_field",
+            Self::NothingToUpdate => "nothing_to_update",
+            Self::InvalidForwardUrl { .. } => "invalid_forward_url",
+            Self::InvalidTimeRange { .. } => "invalid_time_range",
+            Self::UnboundedRange => "unbounded_range",
+            Self::InvalidIds => "invalid_ids",
+            Self::NoSensorIds => "no_sensor_ids",
+            Self::TooManyIds { .. } => "too_many_ids",
+            Self::InvalidInterval => "invalid_interval",
+            Self::PasswordTooShort { .. } => "password_too_short",
+            Self::PasswordTooSimple => "password_too_simple",
+            Self::PasswordIsUsername => "password_is_username",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::WrongPassword => "wrong_password",
+            Self::InvalidRefreshToken => "invalid_refresh_token",
+            Self::UsernameTaken => "username_taken",
+            Self::SensorNotFound => "sensor_not_found",
+            Self::ReadingNotFound => "reading_not_found",
+            Self::NoParticulateReadings => "no_particulate_readings",
+            Self::NotOwner => "not_owner",
+            Self::SensorHasReadings => "sensor_has_readings",
+            Self::AdminRequired => "admin_required",
+            Self::DeadLetterNotFound => "dead_letter_not_found",
+            Self::AlreadyAnchored => "already_anchored",
+            Self::TooManyRows { .. } => "too_many_rows",
+            Self::WalletDepleted => "wallet_depleted",
+            Self::ProofQueueFull => "proof_queue_full",
+            Self::DatabaseTimeout => "database_timeout",
+            Self::Internal => "internal_error",
+        }
+    }
+
+    /// What the client needs to recover, sent as the response body
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            Self::OutOfOrder { last_accepted } => Some(json!({ "last_accepted": last_accepted })),
+            Self::RateLimited { retry_after } => {
+                Some(json!({ "retry_after_secs": retry_after.as_secs_f64() }))
+            }
+            Self::TooSoon { next_allowed } => Some(json!({ "next_allowed": next_allowed })),
+            _ => None,
+        }
+    }
+}
+
+/// PostgreSQL `query_canceled`, raised when `statement_timeout` elapses
+const QUERY_CANCELED: &str = "57014";
+
+impl ApiError {
+    /// Classifies a database failure, already logged by the caller
+    pub fn from_db(e: &sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::Database(e) if e.code().as_deref() == Some(QUERY_CANCELED) => {
+                Self::DatabaseTimeout
+            }
+            sqlx::Error::PoolTimedOut => Self::DatabaseTimeout,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPayload { reason } => write!(f, "{}", reason),
+            Self::TestModeDisabled => write!(f, "Test mode is disabled on this server"),
+            Self::TooManyReadings { what, max } => {
+                write!(f, "{} are limited to {} readings", what, max)
+            }
+            Self::StreamTooLarge { limit } => write!(f, "Streams are limited to {} bytes", limit),
+            Self::RejectedLine { line, reason } => write!(f, "Line {}: {}", line, reason),
+            Self::InvalidReadings { count } => {
+                write!(f, "{} invalid readings, nothing was stored", count)
+            }
+            Self::MissingApiKey => write!(f, "Missing sensor API key"),
+            Self::InvalidApiKey => write!(f, "Invalid sensor API key"),
+            Self::MissingSignature => write!(f, "Missing reading signature"),
+            Self::InvalidSignature => write!(f, "Invalid reading signature"),
+            Self::SignedFramesUnsupported => write!(
+                f,
+                "Sensor requires signed readings, which WebSocket ingest can't carry"
+            ),
+            Self::SignedStreamNotAtomic => write!(f, "Signed streams must use mode=atomic"),
+            Self::MixedSignedStream => write!(
+                f,
+                "Signed streams must hold the readings of a single sensor"
+            ),
+            Self::ForeignReading => write!(f, "Reading belongs to another sensor"),
+            Self::InvalidNonce => write!(f, "Invalid proof-of-work nonce"),
+            Self::SensorDisabled => write!(f, "Sensor is disabled"),
+            Self::SensorTypeBlocked => write!(f, "Sensor type is blocked"),
+            Self::OutOfOrder { .. } => {
+                write!(f, "Reading is not newer than the sensor's latest reading")
+            }
+            Self::DuplicateReading => write!(f, "Duplicate reading"),
+            Self::RateLimited { .. } => {
+                write!(f, "Too many readings from this sensor, slow down")
+            }
+            Self::TooSoon { .. } => write!(
+                f,
+                "Reading arrived before the sensor's minimum sampling interval"
+            ),
+            Self::InvalidSeq => write!(f, "Invalid seq value, must be non-negative"),
+            Self::InvalidValue { field } => write!(f, "Invalid {} value", field),
+            Self::BelowMinimum { field, min } => write!(f, "{} below minimum of {}", field, min),
+            Self::AboveMaximum { field, max } => write!(f, "{} above maximum of {}", field, max),
+            Self::StaleReading => write!(f, "Reading is older than the accepted tolerance"),
+            Self::TimestampTooOld { max_age_days } => write!(
+                f,
+                "Timestamp is more than {} days in the past, check the sensor clock",
+                max_age_days
+            ),
+            Self::TimestampInFuture => {
+                write!(f, "Timestamp is in the future, check the sensor clock")
+            }
+            Self::InvalidSensorField { field } => {
+                write!(f, "Invalid {}, must be 1 to 255 characters", field)
+            }
+            Self::NothingToUpdate => write!(
+                f,
+                "Nothing to update, provide a name, a location, enabled, forward_url or \
+                 humidity_compensation"
+            ),
+            Self::InvalidForwardUrl { reason } => write!(f, "Invalid forward_url: {}", reason),
+            Self::InvalidTimeRange { from, to } => {
+                write!(f, "Invalid time range: {} is after {}", from, to)
+            }
+            Self::UnboundedRange => write!(
+                f,
+                "range=all is only available aggregated, use /readings/aggregate with a \
+                 bucket, or bound the range with from instead"
+            ),
+            Self::InvalidIds => write!(f, "Invalid ids, use e.g. ids=1,2,5"),
+            Self::NoSensorIds => write!(f, "No sensor_ids given"),
+            Self::TooManyIds { field, max } => {
+                write!(f, "Too many {}, at most {} allowed", field, max)
+            }
+            Self::InvalidInterval => write!(f, "Invalid expected_interval, use e.g. 30s, 5m or 1h"),
+            Self::PasswordTooShort { min_len } => {
+                write!(f, "Password must be at least {} characters long", min_len)
+            }
+            Self::PasswordTooSimple => {
+                write!(f, "Password must contain at least one letter and one digit")
+            }
+            Self::PasswordIsUsername => write!(f, "Password must not be the username"),
+            Self::InvalidCredentials => write!(f, "Invalid credentials"),
+            Self::WrongPassword => write!(f, "Invalid password"),
+            Self::InvalidRefreshToken => write!(f, "Invalid or expired refresh token"),
+            Self::UsernameTaken => write!(f, "Username already taken"),
+            Self::SensorNotFound => write!(f, "Sensor is not registered"),
+            Self::ReadingNotFound => write!(f, "Reading is not found"),
+            Self::NoParticulateReadings => {
+                write!(f, "No PM2.5 or PM10 readings in the last 24 hours")
+            }
+            Self::NotOwner => write!(f, "Not authorized to access this sensor"),
+            Self::SensorHasReadings => write!(
+                f,
+                "Sensor has readings, delete with force=true to remove them too"
+            ),
+            Self::AdminRequired => write!(f, "Administrator role required"),
+            Self::DeadLetterNotFound => write!(f, "Dead letter is not found"),
+            Self::AlreadyAnchored => write!(f, "Reading is already anchored on-chain"),
+            Self::TooManyRows { what } => {
+                write!(
+                    f,
+                    "Too many {} in a single response, narrow the query",
+                    what
+                )
+            }
+            Self::WalletDepleted => write!(
+                f,
+                "Proof submission is suspended: the Solana wallet is out of funds"
+            ),
+            Self::ProofQueueFull => {
+                write!(f, "Too many readings awaiting their proof, retry later")
+            }
+            Self::DatabaseTimeout => write!(f, "Database is busy, retry later"),
+            Self::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        HttpResponse::error(self).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_timeouts_are_retryable() {
+        let e = ApiError::from_db(&sqlx::Error::PoolTimedOut);
+        assert!(matches!(e, ApiError::DatabaseTimeout));
+        assert_eq!(e.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let e = ApiError::from_db(&sqlx::Error::RowNotFound);
+        assert!(matches!(e, ApiError::Internal));
+        assert_eq!(e.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn recovery_details_only_for_retryable_errors() {
+        let e = ApiError::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        };
+        assert_eq!(e.details(), Some(json!({ "retry_after_secs": 1.5 })));
+        assert_eq!(ApiError::SensorNotFound.details(), None);
+    }
+}
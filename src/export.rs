@@ -0,0 +1,129 @@
+use crate::db::SensorReadingRecord;
+use arrow_array::{
+    ArrayRef, BooleanArray, Float32Array, Int32Array, RecordBatch, StringArray,
+    TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::AsyncArrowWriter;
+use parquet::file::properties::WriterProperties;
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio_stream::{Stream, StreamExt};
+
+/// Readings per Parquet row group, which is all an export holds in memory at once
+const ROW_GROUP_SIZE: usize = 8192;
+
+/// Typed columns of an exported reading, mirroring `SensorReadingRecord`
+fn readings_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int32, false),
+        Field::new("sensor_id", DataType::Int32, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("co2", DataType::Float32, false),
+        Field::new("temperature", DataType::Float32, false),
+        Field::new("pressure", DataType::Float32, true),
+        Field::new("wind_speed", DataType::Float32, true),
+        Field::new("wind_direction", DataType::Float32, true),
+        Field::new("pm25", DataType::Float32, true),
+        Field::new("pm10", DataType::Float32, true),
+        Field::new("humidity", DataType::Float32, true),
+        Field::new("co2_compensated", DataType::Float32, true),
+        Field::new("backfilled", DataType::Boolean, false),
+        Field::new("tx_signature", DataType::Utf8, false),
+    ]))
+}
+
+/// Writes the readings as a Parquet file, one row group at a time as they stream in
+pub async fn write_readings_parquet<S, W>(mut readings: S, writer: W) -> anyhow::Result<()>
+where
+    S: Stream<Item = Result<SensorReadingRecord, sqlx::Error>> + Unpin,
+    W: AsyncWrite + Unpin + Send,
+{
+    let schema = readings_schema();
+    let props = WriterProperties::builder()
+        .set_max_row_group_size(ROW_GROUP_SIZE)
+        .build();
+    let mut writer = AsyncArrowWriter::try_new(writer, schema.clone(), Some(props))?;
+
+    let mut rows = Vec::with_capacity(ROW_GROUP_SIZE);
+    while let Some(reading) = readings.next().await {
+        rows.push(reading?);
+        if rows.len() == ROW_GROUP_SIZE {
+            writer.write(&record_batch(&schema, &rows)?).await?;
+            rows.clear();
+        }
+    }
+    if !rows.is_empty() {
+        writer.write(&record_batch(&schema, &rows)?).await?;
+    }
+    writer.close().await?;
+    Ok(())
+}
+
+/// Transposes readings into the schema's columns
+fn record_batch(schema: &SchemaRef, rows: &[SensorReadingRecord]) -> anyhow::Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int32Array::from_iter_values(rows.iter().map(|r| r.id))),
+        Arc::new(Int32Array::from_iter_values(
+            rows.iter().map(|r| r.sensor_id),
+        )),
+        Arc::new(
+            TimestampMicrosecondArray::from_iter_values(
+                rows.iter().map(|r| r.timestamp.timestamp_micros()),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(Float32Array::from_iter_values(rows.iter().map(|r| r.co2))),
+        Arc::new(Float32Array::from_iter_values(
+            rows.iter().map(|r| r.temperature),
+        )),
+        Arc::new(Float32Array::from_iter(rows.iter().map(|r| r.pressure))),
+        Arc::new(Float32Array::from_iter(rows.iter().map(|r| r.wind_speed))),
+        Arc::new(Float32Array::from_iter(
+            rows.iter().map(|r| r.wind_direction),
+        )),
+        Arc::new(Float32Array::from_iter(rows.iter().map(|r| r.pm25))),
+        Arc::new(Float32Array::from_iter(rows.iter().map(|r| r.pm10))),
+        Arc::new(Float32Array::from_iter(rows.iter().map(|r| r.humidity))),
+        Arc::new(Float32Array::from_iter(
+            rows.iter().map(|r| r.co2_compensated),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|r| Some(r.backfilled)),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|r| r.tx_signature.as_str()),
+        )),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Writes the readings as CSV, one line at a time as they stream in
+pub async fn write_readings_csv<S, W>(mut readings: S, writer: W) -> anyhow::Result<()>
+where
+    S: Stream<Item = Result<SensorReadingRecord, sqlx::Error>> + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut writer = BufWriter::new(writer);
+    writer
+        .write_all(b"id,sensor_id,timestamp,co2,temperature\n")
+        .await?;
+    while let Some(reading) = readings.next().await {
+        let r = reading?;
+        let line = format!(
+            "{},{},{},{},{}\n",
+            r.id,
+            r.sensor_id,
+            r.timestamp.to_rfc3339(),
+            r.co2,
+            r.temperature
+        );
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
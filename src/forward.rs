@@ -0,0 +1,234 @@
+use crate::config::ForwardSettings;
+use crate::db::SensorReading;
+use crate::error::ApiError;
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// Upper bound for a single forwarding request, so stuck endpoints release their permit
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Consecutive failures of a forwarding endpoint, and when its circuit was opened
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Fire-and-forget fan-out of accepted readings to per-sensor `forward_url`s
+pub struct Forwarder {
+    http: reqwest::Client,
+    permits: Arc<Semaphore>,
+    breakers: Mutex<HashMap<String, Breaker>>,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl Forwarder {
+    pub fn new(settings: &ForwardSettings) -> anyhow::Result<Self> {
+        // Redirects would bypass the check of the target, and so would a hostname
+        // resolving to an internal address
+        let http = reqwest::Client::builder()
+            .timeout(FORWARD_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()?;
+        Ok(Self {
+            http,
+            permits: Arc::new(Semaphore::new(settings.max_in_flight)),
+            breakers: Mutex::new(HashMap::new()),
+            failure_threshold: settings.failure_threshold,
+            cooldown: settings.cooldown,
+        })
+    }
+
+    /// POSTs the reading to `url` on a background task. Readings are dropped, never queued,
+    /// when all permits are taken or the endpoint's circuit is open.
+    pub fn forward(self: &Arc<Self>, url: String, reading: SensorReading) {
+        // Targets stored before validation existed, or written to the database directly
+        if let Err(e) = check_url(&url) {
+            tracing::warn!("Not forwarding to {}: {}", url, e);
+            return;
+        }
+        if !self.circuit_closed(&url) {
+            tracing::debug!("Forwarding to {} is suspended, dropping reading", url);
+            return;
+        }
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            tracing::warn!("Too many readings in flight, dropping forward to {}", url);
+            return;
+        };
+
+        let forwarder = Arc::clone(self);
+        tokio::spawn(async move {
+            let result = forwarder
+                .http
+                .post(&url)
+                .json(&reading)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            drop(permit);
+
+            match result {
+                Ok(_) => forwarder.record(&url, true),
+                Err(e) => {
+                    tracing::warn!("Error forwarding reading to {}: {}", url, e);
+                    forwarder.record(&url, false);
+                }
+            }
+        });
+    }
+
+    /// Whether the endpoint may be called: its circuit is closed, or the cooldown elapsed
+    /// and a single probe request is let through
+    fn circuit_closed(&self, url: &str) -> bool {
+        let mut breakers = self.breakers.lock().unwrap();
+        let Some(breaker) = breakers.get_mut(url) else {
+            return true;
+        };
+        match breaker.opened_at {
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => false,
+            Some(_) => {
+                // Half-open: a failing probe reopens the circuit for another cooldown
+                breaker.opened_at = Some(Instant::now());
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Updates the endpoint's breaker with the outcome of a request
+    fn record(&self, url: &str, success: bool) {
+        let mut breakers = self.breakers.lock().unwrap();
+        if success {
+            breakers.remove(url);
+            return;
+        }
+        let breaker = breakers.entry(url.to_string()).or_default();
+        breaker.failures += 1;
+        if breaker.failures >= self.failure_threshold && breaker.opened_at.is_none() {
+            tracing::warn!(
+                "Suspending forwarding to {} after {} consecutive failures",
+                url,
+                breaker.failures
+            );
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Validates a `forward_url` before it's stored. The server POSTs readings there, so only
+/// http(s) URLs to public hosts are accepted, never loopback or private network targets.
+pub fn check_url(url: &str) -> Result<(), ApiError> {
+    let url = Url::parse(url).map_err(|_| ApiError::InvalidForwardUrl {
+        reason: "not a valid URL",
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ApiError::InvalidForwardUrl {
+            reason: "only http and https are supported",
+        });
+    }
+    let Some(host) = url.host_str() else {
+        return Err(ApiError::InvalidForwardUrl {
+            reason: "missing host",
+        });
+    };
+    // IPv6 literals keep their brackets
+    let internal = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+    };
+    if internal {
+        return Err(ApiError::InvalidForwardUrl {
+            reason: "private and loopback hosts are not allowed",
+        });
+    }
+    Ok(())
+}
+
+/// Whether the address is routable on the public internet
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local() // Including cloud metadata endpoints, 169.254.169.254
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)) // Carrier-grade NAT, 100.64.0.0/10
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00 // Unique local, fc00::/7
+                || (first & 0xffc0) == 0xfe80) // Link-local, fe80::/10
+        }
+    }
+}
+
+/// Drops internal addresses from DNS answers, since a public-looking hostname can still
+/// resolve to one, or change to one after `check_url` accepted it
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_http_urls_accepted() {
+        assert!(check_url("https://influx.example.com/api/v2/write").is_ok());
+        assert!(check_url("http://203.0.114.7:8086/write").is_ok());
+    }
+
+    #[test]
+    fn internal_targets_rejected() {
+        for url in [
+            "ftp://example.com/readings",
+            "not a url",
+            "http://localhost:8086/write",
+            "http://127.0.0.1/",
+            "http://10.0.0.5/",
+            "http://192.168.1.20/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(check_url(url).is_err(), "{} accepted", url);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::db::ReadingStats;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+/// Relative weight of each factor in the composite score, only their ratios matter
+#[derive(Debug)]
+pub struct HealthWeights {
+    pub freshness: f64,
+    pub outliers: f64,
+    pub proofs: f64,
+    pub sequence: f64,
+}
+
+/// Composite 0-100 health score of a sensor, with the factors it was derived from
+#[derive(Debug, Serialize)]
+pub struct SensorHealth {
+    score: u8,
+    factors: HealthFactors,
+}
+
+/// Each factor ranges from 0 (worst) to 1 (best)
+#[derive(Debug, Serialize)]
+struct HealthFactors {
+    freshness: f64, // expected_interval / age of the latest reading, capped at 1
+    outliers: f64,  // Share of readings within 3 standard deviations of the window mean
+    proofs: f64,    // Share of readings anchored on-chain
+    sequence: f64,  // Share of firmware sequence numbers received, 1 without sequence numbers
+}
+
+/// Scores the readings of a time window. A sensor without readings in the window scores 0.
+pub fn score(
+    stats: &ReadingStats,
+    expected_interval: Duration,
+    now: DateTime<Utc>,
+    weights: &HealthWeights,
+) -> SensorHealth {
+    let factors = match stats.last_reading_at {
+        Some(last_reading_at) if stats.readings > 0 => {
+            let readings = stats.readings as f64;
+            let expected = expected_interval.num_seconds() as f64;
+            let age = (now - last_reading_at).num_seconds() as f64;
+            let received = stats.sequenced as f64;
+            HealthFactors {
+                freshness: expected / age.max(expected),
+                outliers: 1.0 - stats.outliers as f64 / readings,
+                proofs: stats.proven as f64 / readings,
+                sequence: match stats.sequenced {
+                    0 => 1.0,
+                    _ => received / (received + stats.missing_sequences as f64),
+                },
+            }
+        }
+        _ => HealthFactors {
+            freshness: 0.0,
+            outliers: 0.0,
+            proofs: 0.0,
+            sequence: 0.0,
+        },
+    };
+
+    let total = weights.freshness + weights.outliers + weights.proofs + weights.sequence;
+    let weighted = weights.freshness * factors.freshness
+        + weights.outliers * factors.outliers
+        + weights.proofs * factors.proofs
+        + weights.sequence * factors.sequence;
+    let score = (100.0 * weighted / total).round().clamp(0.0, 100.0) as u8;
+
+    SensorHealth { score, factors }
+}
@@ -1,27 +1,56 @@
-use super::db::UserForm;
+use super::db::{Sensor, SensorReading, TokenOwner};
+use super::error::ApiError;
+use super::request_id;
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug, Deserialize)]
 pub struct TimeRangeQuery {
     range: Option<TimeRange>,
+    from: Option<DateTime<Utc>>, // Overrides the range's lower bound
+    to: Option<DateTime<Utc>>,   // Defaults to now, the range ending there
 }
 
 impl TimeRangeQuery {
-    pub fn to_cutoff_time(&self) -> DateTime<Utc> {
-        let now = Utc::now();
+    /// Returns the `(from, to)` bounds of the query, refusing inverted ones
+    pub fn to_time_bounds(&self) -> Result<(DateTime<Utc>, DateTime<Utc>), ApiError> {
+        // The range ends at `to`, so a past `to` alone moves the whole window back
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or_else(|| self.to_cutoff_time(to));
+        if from > to {
+            return Err(ApiError::InvalidTimeRange { from, to });
+        }
+        Ok((from, to))
+    }
 
+    /// Bounds for endpoints returning raw readings, which refuse the unbounded `all` range:
+    /// a sensor's whole history must be read through aggregation. Refused even alongside
+    /// `from`, which overrides the range anyway, so `all` never silently means less.
+    pub fn to_raw_time_bounds(&self) -> Result<(DateTime<Utc>, DateTime<Utc>), ApiError> {
+        if matches!(self.range, Some(TimeRange::All)) {
+            return Err(ApiError::UnboundedRange);
+        }
+        self.to_time_bounds()
+    }
+
+    fn to_cutoff_time(&self, to: DateTime<Utc>) -> DateTime<Utc> {
         let range = match self.range {
-            None => return now - Duration::days(1), // Default is one day
+            None => return to - Duration::days(1), // Default is one day
             Some(range) => range,
         };
 
         match range {
-            TimeRange::OneDay => now - Duration::days(1),
-            TimeRange::OneWeek => now - Duration::weeks(1),
-            TimeRange::OneMonth => now - Duration::days(30),
-            TimeRange::OneQuarter => now - Duration::days(90),
-            TimeRange::All => now - Duration::days(180), // Max 6 months
+            TimeRange::OneDay => to - Duration::days(1),
+            TimeRange::OneWeek => to - Duration::weeks(1),
+            TimeRange::OneMonth => to - Duration::days(30),
+            TimeRange::OneQuarter => to - Duration::days(90),
+            TimeRange::SixMonths => to - Duration::days(180),
+            // No reading predates the epoch, and PostgreSQL can't store chrono's minimum
+            TimeRange::All => DateTime::UNIX_EPOCH,
         }
     }
 }
@@ -37,10 +66,338 @@ enum TimeRange {
     OneMonth,
     #[serde(rename = "90d")]
     OneQuarter,
+    #[serde(rename = "180d")]
+    SixMonths,
+    /// Every reading, refused by raw endpoints: only aggregates cover it
     #[serde(rename = "all")]
     All,
 }
 
+/// Upper bound for the number of readings returned by the "latest N" shortcut
+const MAX_RECENT_READINGS: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct RecentQuery {
+    n: Option<i64>,
+}
+
+impl RecentQuery {
+    pub fn count(&self) -> i64 {
+        self.n.unwrap_or(50).clamp(1, MAX_RECENT_READINGS)
+    }
+}
+
+/// Upper bound for the number of ids in a single sensors lookup
+const MAX_SENSOR_IDS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SensorsQuery {
+    ids: Option<String>, // e.g. "1,2,5"
+}
+
+impl SensorsQuery {
+    /// Requested sensor ids, `None` to list every sensor of the user
+    pub fn ids(&self) -> Result<Option<Vec<i32>>, ApiError> {
+        let Some(ids) = self.ids.as_deref() else {
+            return Ok(None);
+        };
+        let ids = ids
+            .split(',')
+            .map(|id| id.trim().parse())
+            .collect::<Result<Vec<i32>, _>>()
+            .map_err(|_| ApiError::InvalidIds)?;
+        if ids.len() > MAX_SENSOR_IDS {
+            return Err(ApiError::TooManyIds {
+                field: "ids",
+                max: MAX_SENSOR_IDS,
+            });
+        }
+        Ok(Some(ids))
+    }
+}
+
+/// Body of the multi-sensor readings lookup, the time range sharing `TimeRangeQuery`'s fields
+#[derive(Debug, Deserialize)]
+pub struct MultiReadingsRequest {
+    sensor_ids: Vec<i32>,
+    #[serde(flatten)]
+    pub range: TimeRangeQuery,
+}
+
+impl MultiReadingsRequest {
+    /// Requested sensor ids, deduplicated
+    pub fn ids(&self) -> Result<Vec<i32>, ApiError> {
+        let mut ids = self.sensor_ids.clone();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.is_empty() {
+            return Err(ApiError::NoSensorIds);
+        }
+        if ids.len() > MAX_SENSOR_IDS {
+            return Err(ApiError::TooManyIds {
+                field: "sensor_ids",
+                max: MAX_SENSOR_IDS,
+            });
+        }
+        Ok(ids)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProofStatusQuery {
+    #[serde(default)]
+    proof_status: ProofStatus,
+}
+
+impl ProofStatusQuery {
+    /// Whether readings must have (`Some(true)`) or lack (`Some(false)`) a recorded proof
+    pub fn proven(&self) -> Option<bool> {
+        match self.proof_status {
+            ProofStatus::All => None,
+            ProofStatus::Proven => Some(true),
+            ProofStatus::Pending => Some(false),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ProofStatus {
+    #[default]
+    All,
+    Proven,  // Signature recorded
+    Pending, // No signature yet, or given up on
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    #[serde(default)]
+    sort: SensorSort,
+}
+
+impl ActivityQuery {
+    pub fn stale_first(&self) -> bool {
+        matches!(self.sort, SensorSort::Stale)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SensorSort {
+    #[default]
+    Name,
+    Stale, // Least recently active first, never-reporting sensors on top
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateQuery {
+    #[serde(default)]
+    bucket: Bucket,
+}
+
+impl AggregateQuery {
+    /// `date_trunc` field the readings are grouped by
+    pub fn bucket(&self) -> &'static str {
+        match self.bucket {
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Bucket {
+    #[default]
+    Hour,
+    Day,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GapsQuery {
+    expected_interval: Option<String>, // e.g. "30s", "5m", "1h"
+}
+
+impl GapsQuery {
+    pub fn expected_interval(&self) -> Result<Duration, ApiError> {
+        let Some(interval) = self.expected_interval.as_deref() else {
+            return Ok(Duration::minutes(5)); // Default sampling period
+        };
+
+        let unit = interval.chars().last().ok_or(ApiError::InvalidInterval)?;
+        let value: i64 = interval[..interval.len() - unit.len_utf8()]
+            .parse()
+            .map_err(|_| ApiError::InvalidInterval)?;
+        if value <= 0 {
+            return Err(ApiError::InvalidInterval);
+        }
+        let interval = match unit {
+            's' => Duration::try_seconds(value),
+            'm' => Duration::try_minutes(value),
+            'h' => Duration::try_hours(value),
+            'd' => Duration::try_days(value),
+            _ => None,
+        };
+        interval.ok_or(ApiError::InvalidInterval)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PendingProofsQuery {
+    #[serde(default)]
+    older_than_secs: u32, // Only readings pending for at least this long
+}
+
+impl PendingProofsQuery {
+    pub fn cutoff(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::seconds(self.older_than_secs.into())
+    }
+}
+
+/// Body of the bulk cancellation of pending proofs, whose age is required so that recent
+/// readings are never cancelled by accident
+#[derive(Debug, Deserialize)]
+pub struct CancelProofsRequest {
+    older_than_secs: u32,
+}
+
+impl CancelProofsRequest {
+    pub fn cutoff(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::seconds(self.older_than_secs.into())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteSensorQuery {
+    #[serde(default)]
+    pub force: bool, // Also delete the sensor's readings
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountQuery {
+    exact_count: Option<bool>, // false trades precision for speed on huge histories
+}
+
+impl CountQuery {
+    pub fn exact(&self) -> bool {
+        self.exact_count.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchQuery {
+    #[serde(default)]
+    pub mode: BatchMode,
+}
+
+/// Whether a batch with invalid lines is partially stored or not at all
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchMode {
+    #[default]
+    Partial, // Skip and report invalid lines
+    Atomic, // All lines or none
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IngestQuery {
+    format: Option<IngestFormat>,
+    unit: Option<TemperatureUnit>,
+    #[serde(default)]
+    pub test: bool, // Validate only, nothing is stored or submitted
+}
+
+impl IngestQuery {
+    /// Decodes a request body into a reading according to the requested format,
+    /// with its temperature converted to Celsius ahead of range validation
+    pub fn parse_reading(&self, body: &[u8]) -> Result<SensorReading, String> {
+        let reading = self.decode_reading(body)?;
+        let units = UnitQuery { unit: self.unit };
+        units
+            .to_celsius(reading)
+            .map_err(|e| format!("Invalid reading: {}", e))
+    }
+
+    fn decode_reading(&self, body: &[u8]) -> Result<SensorReading, String> {
+        match self.format.unwrap_or(IngestFormat::Json) {
+            IngestFormat::Json => {
+                serde_json::from_slice(body).map_err(|e| format!("Invalid reading: {}", e))
+            }
+            IngestFormat::Compact => {
+                // Positional form: [sensor_id, epoch_ms, co2, temperature]
+                let (sensor_id, epoch_ms, co2, temperature): (i32, i64, f32, f32) =
+                    serde_json::from_slice(body)
+                        .map_err(|e| format!("Invalid compact reading: {}", e))?;
+                let timestamp = DateTime::from_timestamp_millis(epoch_ms)
+                    .ok_or("Invalid compact reading: timestamp out of range")?;
+                Ok(SensorReading {
+                    sensor_id,
+                    timestamp,
+                    co2,
+                    temperature,
+                    pressure: None,
+                    wind_speed: None,
+                    wind_direction: None,
+                    pm25: None,
+                    pm10: None,
+                    humidity: None,
+                    co2_compensated: None,
+                    seq: None,
+                    nonce: None,
+                    backfilled: false,
+                })
+            }
+        }
+    }
+}
+
+/// Temperature unit of every reading of a bulk or WebSocket ingest
+#[derive(Debug, Deserialize)]
+pub struct UnitQuery {
+    unit: Option<TemperatureUnit>, // Celsius when unset
+}
+
+impl UnitQuery {
+    /// Converts the reading's temperature to Celsius, ahead of range validation
+    pub fn to_celsius(&self, mut reading: SensorReading) -> Result<SensorReading, String> {
+        if let Some(unit) = self.unit {
+            reading.temperature = unit.to_celsius(reading.temperature)?;
+        }
+        Ok(reading)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum IngestFormat {
+    Json,    // Keyed object, the primary format
+    Compact, // Positional array for bandwidth-constrained devices
+}
+
+/// Temperature unit a device reports in, Celsius being the stored one
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    fn to_celsius(self, value: f32) -> Result<f32, String> {
+        let celsius = match self {
+            Self::Celsius => value,
+            Self::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+            Self::Kelvin => value - 273.15,
+        };
+        // Negative Kelvin (or its equivalents) is a device fault, not a cold reading
+        if celsius < -273.15 {
+            return Err("temperature below absolute zero".to_string());
+        }
+        Ok(celsius)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HttpResponse<T>
 where
@@ -48,14 +405,82 @@ where
 {
     status: u16,
     error_msg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>, // Stable identifier of an ApiError
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>, // Quoted by users reporting a failed request
     body: Option<T>, // For success payloads
 }
 
+/// Sends the JSON envelope with its `status` as the actual HTTP status code, errors
+/// carrying the id of the request
+impl<T: Serialize> IntoResponse for HttpResponse<T> {
+    fn into_response(mut self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        if status.is_client_error() || status.is_server_error() {
+            self.request_id = request_id::current();
+        }
+        (status, Json(self)).into_response()
+    }
+}
+
+impl<T: Serialize> From<ApiError> for HttpResponse<T> {
+    fn from(e: ApiError) -> Self {
+        let msg = e.to_string();
+        let resp = match e.status() {
+            StatusCode::BAD_REQUEST => Self::bad_request(msg),
+            StatusCode::UNAUTHORIZED => Self::unauthorized(msg),
+            StatusCode::FORBIDDEN => Self::forbidden(msg),
+            StatusCode::NOT_FOUND => Self::not_found(msg),
+            StatusCode::CONFLICT => Self::conflicts(msg),
+            StatusCode::PAYLOAD_TOO_LARGE => Self::payload_too_large(msg),
+            StatusCode::SERVICE_UNAVAILABLE => Self::service_unavailable(msg),
+            StatusCode::INTERNAL_SERVER_ERROR => Self::internal_error(),
+            status => HttpResponse {
+                status: status.as_u16(),
+                error_msg: Some(msg),
+                error_code: None,
+                request_id: None,
+                body: None,
+            },
+        };
+        HttpResponse {
+            error_code: Some(e.code().to_string()),
+            ..resp
+        }
+    }
+}
+
+impl HttpResponse<Value> {
+    /// Error response carrying the error's recovery details, e.g. when to retry
+    pub fn error(e: ApiError) -> Self {
+        let details = e.details();
+        HttpResponse {
+            body: details,
+            ..Self::from(e)
+        }
+    }
+}
+
 impl<T: Serialize> HttpResponse<T> {
+    /// Attaches a payload to any response, e.g. partial progress alongside an error
+    pub fn with_data(mut self, data: T) -> Self {
+        self.body = Some(data);
+        self
+    }
+
+    /// Attaches a payload unless the response already carries one, e.g. a retry hint
+    pub fn or_data(mut self, data: T) -> Self {
+        self.body.get_or_insert(data);
+        self
+    }
+
     pub fn success() -> Self {
         HttpResponse {
             status: 200,
             error_msg: None,
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
@@ -64,6 +489,19 @@ impl<T: Serialize> HttpResponse<T> {
         HttpResponse {
             status: 200,
             error_msg: None,
+            error_code: None,
+            request_id: None,
+            body: Some(data),
+        }
+    }
+
+    /// 201, for a request that stored a new resource
+    pub fn created(data: T) -> Self {
+        HttpResponse {
+            status: 201,
+            error_msg: None,
+            error_code: None,
+            request_id: None,
             body: Some(data),
         }
     }
@@ -72,6 +510,8 @@ impl<T: Serialize> HttpResponse<T> {
         HttpResponse {
             status: 400,
             error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
@@ -80,6 +520,8 @@ impl<T: Serialize> HttpResponse<T> {
         HttpResponse {
             status: 401,
             error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
@@ -88,14 +530,18 @@ impl<T: Serialize> HttpResponse<T> {
         HttpResponse {
             status: 403,
             error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
 
-    pub fn not_found() -> Self {
+    pub fn not_found(msg: impl AsRef<str>) -> Self {
         HttpResponse {
             status: 404,
-            error_msg: Some("Resource is not found".to_string()),
+            error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
@@ -104,6 +550,28 @@ impl<T: Serialize> HttpResponse<T> {
         HttpResponse {
             status: 409,
             error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
+            body: None,
+        }
+    }
+
+    pub fn payload_too_large(msg: impl AsRef<str>) -> Self {
+        HttpResponse {
+            status: 413,
+            error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
+            body: None,
+        }
+    }
+
+    pub fn service_unavailable(msg: impl AsRef<str>) -> Self {
+        HttpResponse {
+            status: 503,
+            error_msg: Some(msg.as_ref().to_string()),
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
@@ -112,24 +580,198 @@ impl<T: Serialize> HttpResponse<T> {
         HttpResponse {
             status: 500,
             error_msg: Some("Internal server error".to_string()),
+            error_code: None,
+            request_id: None,
             body: None,
         }
     }
 }
 
+/// A newly created sensor, with the only copy of its ingest API key
+#[derive(Debug, Serialize)]
+pub struct NewSensor {
+    #[serde(flatten)]
+    pub sensor: Sensor,
+    pub api_key: String,
+    pub signing_secret: String, // Key of the optional X-Signature HMAC
+}
+
+/// Receipt of a stored reading, letting devices verify its proof later on their own
+#[derive(Debug, Serialize)]
+pub struct IngestReceipt {
+    pub reading_id: Option<i32>,   // None in test mode, nothing was stored
+    pub hash: String,              // blake3 digest anchored on-chain
+    pub signature: Option<String>, // None while the proof is queued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>, // Would-be memo, only returned in test mode
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub test: bool,
+}
+
+/// Readings in a time range, `approximate` when taken from the planner's estimate
+#[derive(Debug, Serialize)]
+pub struct ReadingCount {
+    pub count: i64,
+    pub approximate: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
     pub username: String,
     pub role: String,
 }
 
 impl LoginResponse {
-    pub fn new(token: String, user_form: &UserForm) -> Self {
+    pub fn new(token: String, refresh_token: String, owner: TokenOwner) -> Self {
         LoginResponse {
             token,
-            username: user_form.username.clone(),
-            role: "user".to_string(),
+            refresh_token,
+            username: owner.username,
+            role: owner.role,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn fahrenheit_and_celsius_meet_at_minus_forty() {
+        let celsius = TemperatureUnit::Fahrenheit.to_celsius(-40.0).unwrap();
+        assert!(close(celsius, -40.0));
+    }
+
+    #[test]
+    fn freezing_point_converts_to_zero() {
+        assert!(close(
+            TemperatureUnit::Kelvin.to_celsius(273.15).unwrap(),
+            0.0
+        ));
+        assert!(close(
+            TemperatureUnit::Fahrenheit.to_celsius(32.0).unwrap(),
+            0.0
+        ));
+        assert!(close(
+            TemperatureUnit::Celsius.to_celsius(0.0).unwrap(),
+            0.0
+        ));
+    }
+
+    #[test]
+    fn below_absolute_zero_rejected() {
+        assert!(TemperatureUnit::Kelvin.to_celsius(-0.5).is_err());
+        assert!(TemperatureUnit::Fahrenheit.to_celsius(-460.0).is_err());
+        assert!(TemperatureUnit::Celsius.to_celsius(-274.0).is_err());
+        assert!(TemperatureUnit::Kelvin.to_celsius(0.0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn error_response_carries_status_message_and_code() {
+        let e = ApiError::NotOwner;
+        let msg = e.to_string();
+        let resp = e.into_response();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], 403);
+        assert_eq!(body["error_msg"], msg);
+        assert_eq!(body["error_code"], "not_owner");
+    }
+
+    #[test]
+    fn internal_error_hides_details() {
+        let resp = HttpResponse::<()>::from(ApiError::Internal);
+        assert_eq!(resp.status, 500);
+        assert_eq!(resp.error_msg.as_deref(), Some("Internal server error"));
+        assert_eq!(resp.error_code.as_deref(), Some("internal_error"));
+    }
+
+    #[test]
+    fn error_carries_recovery_details() {
+        let retry_after = std::time::Duration::from_secs(3);
+        let resp = HttpResponse::error(ApiError::RateLimited { retry_after });
+        assert_eq!(resp.status, 429);
+        assert_eq!(
+            resp.body,
+            Some(serde_json::json!({ "retry_after_secs": 3.0 }))
+        );
+
+        // Progress is only attached when no retry hint takes its place
+        let resp = resp.or_data(serde_json::json!({ "accepted": 2 }));
+        assert_eq!(
+            resp.body,
+            Some(serde_json::json!({ "retry_after_secs": 3.0 }))
+        );
+        let resp = HttpResponse::error(ApiError::InvalidSignature).or_data(Value::from(2));
+        assert_eq!(resp.body, Some(Value::from(2)));
+    }
+
+    #[test]
+    fn raw_endpoints_refuse_range_all() {
+        let from = Utc::now() - Duration::days(400);
+        for from in [None, Some(from)] {
+            let query = TimeRangeQuery {
+                range: Some(TimeRange::All),
+                from,
+                to: None,
+            };
+            assert!(matches!(
+                query.to_raw_time_bounds(),
+                Err(ApiError::UnboundedRange)
+            ));
+            assert!(query.to_time_bounds().is_ok());
+        }
+    }
+
+    #[test]
+    fn range_ends_at_past_to() {
+        let to = Utc::now() - Duration::weeks(1);
+        let query = TimeRangeQuery {
+            range: Some(TimeRange::OneWeek),
+            from: None,
+            to: Some(to),
+        };
+        assert_eq!(
+            query.to_time_bounds().unwrap(),
+            (to - Duration::weeks(1), to)
+        );
+
+        let query = TimeRangeQuery {
+            range: None,
+            from: None,
+            to: Some(to),
+        };
+        assert_eq!(
+            query.to_time_bounds().unwrap(),
+            (to - Duration::days(1), to)
+        );
+    }
+
+    #[test]
+    fn batch_temperatures_converted_to_celsius() {
+        let uri = "/sensors/ingest/batch?unit=fahrenheit".parse().unwrap();
+        let Query(units) = Query::<UnitQuery>::try_from_uri(&uri).unwrap();
+        let batch = r#"[
+            {"sensor_id": 1, "timestamp": "2026-01-01T00:00:00Z", "co2": 400, "temperature": -40},
+            {"sensor_id": 1, "timestamp": "2026-01-01T00:01:00Z", "co2": 400, "temperature": 212}
+        ]"#;
+        let readings: Vec<SensorReading> = serde_json::from_str(batch).unwrap();
+        let celsius: Vec<f32> = readings
+            .into_iter()
+            .map(|reading| units.to_celsius(reading).unwrap().temperature)
+            .collect();
+        assert!(close(celsius[0], -40.0));
+        assert!(close(celsius[1], 100.0));
+    }
+}
@@ -0,0 +1,106 @@
+/// Number of lamports in one SOL
+pub const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+/// Minimum wallet balance required to keep issuing transactions (0.001 SOL)
+pub const MIN_BALANCE: u64 = 1_000_000;
+
+/// Decimal places of a SOL amount, one per power of ten in `LAMPORTS_PER_SOL`
+const SOL_DECIMALS: usize = 9;
+
+/// Formats a lamport amount as SOL using integer arithmetic, so no precision is lost
+pub fn format_sol(lamports: u64) -> String {
+    format!(
+        "{}.{:09}",
+        lamports / LAMPORTS_PER_SOL,
+        lamports % LAMPORTS_PER_SOL
+    )
+}
+
+/// Whole SOL in lamports, `None` on overflow
+pub fn sol_to_lamports(sol: u64) -> Option<u64> {
+    sol.checked_mul(LAMPORTS_PER_SOL)
+}
+
+/// Parses a decimal SOL amount like "0.001" into lamports exactly, refusing more than
+/// nine decimals, signs, and amounts that don't fit in a u64
+pub fn parse_sol(sol: &str) -> Option<u64> {
+    let (whole, fraction) = sol.split_once('.').unwrap_or((sol, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > SOL_DECIMALS
+        || !digits(whole)
+        || !digits(fraction)
+    {
+        return None;
+    }
+    let whole = match whole {
+        "" => 0,
+        whole => sol_to_lamports(whole.parse().ok()?)?,
+    };
+    let fraction = match fraction {
+        "" => 0,
+        fraction => format!("{:0<width$}", fraction, width = SOL_DECIMALS)
+            .parse::<u64>()
+            .ok()?,
+    };
+    whole.checked_add(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_sol_keeps_every_lamport() {
+        assert_eq!(format_sol(0), "0.000000000");
+        assert_eq!(format_sol(MIN_BALANCE), "0.001000000");
+        assert_eq!(format_sol(1_500_000_001), "1.500000001");
+        assert_eq!(format_sol(u64::MAX), "18446744073.709551615");
+    }
+
+    #[test]
+    fn sol_to_lamports_checks_overflow() {
+        assert_eq!(sol_to_lamports(0), Some(0));
+        assert_eq!(sol_to_lamports(2), Some(2 * LAMPORTS_PER_SOL));
+        assert_eq!(
+            sol_to_lamports(18_446_744_073),
+            Some(18_446_744_073_000_000_000)
+        );
+        assert_eq!(sol_to_lamports(18_446_744_074), None);
+    }
+
+    #[test]
+    fn parse_sol_is_exact() {
+        assert_eq!(parse_sol("1"), Some(LAMPORTS_PER_SOL));
+        assert_eq!(parse_sol("0.001"), Some(MIN_BALANCE));
+        assert_eq!(parse_sol(".5"), Some(500_000_000));
+        assert_eq!(parse_sol("3."), Some(3 * LAMPORTS_PER_SOL));
+        assert_eq!(parse_sol("0.000000001"), Some(1));
+    }
+
+    #[test]
+    fn parse_sol_rejects_malformed_amounts() {
+        for sol in [
+            "",
+            ".",
+            "-1",
+            "+1",
+            "1.0000000001",
+            "1e9",
+            "1.2.3",
+            " 1",
+            "abc",
+        ] {
+            assert_eq!(parse_sol(sol), None, "{:?}", sol);
+        }
+        assert_eq!(parse_sol("18446744074"), None);
+        assert_eq!(parse_sol("18446744073.709551616"), None);
+    }
+
+    #[test]
+    fn parse_sol_round_trips_format_sol() {
+        for lamports in [0, 1, MIN_BALANCE, 1_500_000_001, u64::MAX] {
+            assert_eq!(parse_sol(&format_sol(lamports)), Some(lamports));
+        }
+    }
+}
@@ -1,17 +1,37 @@
 mod api;
+mod aqi;
 mod auth;
+mod config;
 mod crypto;
 mod db;
+mod error;
+mod export;
+mod forward;
+mod health;
 mod http;
+mod lamports;
+mod proofs;
+mod ratelimit;
+mod request_id;
 mod solana;
+mod telemetry;
 
+use axum::extract::DefaultBodyLimit;
+use axum::http::{HeaderName, Method, header};
+use axum::middleware;
 use axum::routing::post;
 use axum::{Router, routing::get};
 use axum_server::tls_rustls::RustlsConfig;
+use config::Config;
 use rustls::crypto::{CryptoProvider, ring};
 use solana::SolanaClient;
 use sqlx::postgres::PgPoolOptions;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::sync::mpsc;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing_subscriber::EnvFilter;
+
+/// Log filter used when `RUST_LOG` is unset: crate events at info, dependencies at warn
+const DEFAULT_LOG_FILTER: &str = "warn,PollutionTracker=info";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -20,48 +40,134 @@ async fn main() -> anyhow::Result<()> {
 
     // Load environment variables
     dotenv::dotenv().ok();
-    let db = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let _ = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+
+    // Initialize logging, honoring RUST_LOG directives when present
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    // Load configuration (secrets are redacted from the dump)
+    let config = Config::from_env()?;
+    tracing::debug!("Effective configuration: {:#?}", config);
+    tracing::info!("Enabled features: {:?}", config.features.enabled());
 
     // Initialize Solana client
-    let rpc_url = std::env::var("SOLANA_RPC").expect("RPC url must be set");
-    let keypair = std::env::var("SOLANA_KEYPAIR").expect("Solana keypair must be set");
-    let client = SolanaClient::new(&rpc_url, &keypair)?;
+    let client = SolanaClient::new(
+        &config.solana_rpc,
+        &config.solana_keypair,
+        config.proof_scheme,
+        config.memo_version,
+        config.min_balance,
+        config.funds_recheck,
+        config.confirm,
+    )?;
+    tracing::debug!("Solana commitment: {:?}", client.rpc_client.commitment());
     client.test_connection().await?;
-    anyhow::ensure!(client.enough_balance()?, "Insufficient balance");
+    anyhow::ensure!(client.enough_balance().await?, "Insufficient balance");
+
+    // Resolve unqualified table names within the configured schema, and cancel statements
+    // running past the timeout so a stuck query can't hold a request forever
+    let session = [
+        format!("SET search_path TO \"{}\"", config.db_schema),
+        format!(
+            "SET statement_timeout = {}",
+            config.db_statement_timeout.as_millis()
+        ),
+    ];
 
     // Connect to database
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&db)
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(config.db_connect_timeout)
+        .after_connect(move |conn, _meta| {
+            let session = session.clone();
+            Box::pin(async move {
+                for statement in &session {
+                    sqlx::query(statement).execute(&mut *conn).await?;
+                }
+                Ok(())
+            })
+        })
+        .connect(&config.database_url)
         .await
         .expect("Failed to connect to database");
 
-    let app_state = api::AppState::new(pool, client);
+    // Create or upgrade the schema before serving
+    sqlx::migrate!().run(&pool).await?;
 
-    // Allow requests from any origin (development-purposes only)
+    let addr = config.bind_addr;
+    let metrics = telemetry::install()?;
+    let (proofs, jobs) = mpsc::channel(config.proof_queue_capacity);
+    let app_state = api::AppState::new(pool, client, config, metrics, proofs)?;
+
+    // Anchor queued readings in the background, off the ingest path
+    tokio::spawn(proofs::run(
+        jobs,
+        app_state.client.clone(),
+        app_state.pool.clone(),
+    ));
+
+    // Allow the configured origins only, any origin is reserved to development
+    let origins = if app_state.config.cors.dev_mode {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(app_state.config.cors.allowed_origins.clone())
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("x-signature"),
+            request_id::REQUEST_ID_HEADER,
+        ])
+        .expose_headers([request_id::REQUEST_ID_HEADER]);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(api::root))
         .route("/health", get(api::db_health_check))
+        .route("/health/solana", get(api::solana_health_check))
+        .route("/metrics", get(api::metrics))
         .route("/users/register", post(api::user_registry))
         .route("/users/login", post(api::user_login))
-        .route("/sensors/ingest", post(api::ingest_reading))
+        .route("/auth/refresh", post(api::refresh_token))
+        .route("/auth/logout", post(api::logout))
+        // Oversized ingest bodies are refused with 413 before being buffered
+        .route(
+            "/sensors/ingest",
+            post(api::ingest_reading)
+                .layer(DefaultBodyLimit::max(app_state.config.ingest_body_limit)),
+        );
+    if app_state.config.features.batch_ingest {
+        let limit = DefaultBodyLimit::max(app_state.config.batch_body_limit);
+        app = app.route(
+            "/sensors/ingest/batch",
+            post(api::ingest_batch).layer(limit),
+        );
+    }
+    if app_state.config.features.stream_ingest {
+        app = app.route("/sensors/ingest/stream", post(api::ingest_stream));
+    }
+    if app_state.config.features.websocket_ingest {
+        app = app.route("/sensors/{sensor_id}/ingest/ws", get(api::ingest_socket));
+    }
+
+    let app = app
         // Merge protected routes as a separate router
-        .merge(api::protected_routes())
+        .merge(api::protected_routes(&app_state))
         .layer(cors)
+        // Outermost, so every response and log line carries the request id
+        .layer(middleware::from_fn(request_id::propagate))
         .with_state(app_state);
 
     // Load TLS config
-    let config = RustlsConfig::from_pem_file("localhost+2.pem", "localhost+2-key.pem").await?;
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 3000));
+    let tls = RustlsConfig::from_pem_file("localhost+2.pem", "localhost+2-key.pem").await?;
 
     // Run server
-    axum_server::bind_rustls(addr, config)
+    axum_server::bind_rustls(addr, tls)
         .serve(app.into_make_service())
         .await?;
 
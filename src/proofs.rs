@@ -0,0 +1,71 @@
+use crate::db::{self, SensorLabel, SensorReading};
+use crate::solana::{SolanaClient, SubmitError};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Stored reading awaiting its Solana proof
+pub struct ProofJob {
+    pub reading_id: i32,
+    pub reading: SensorReading,
+    pub label: SensorLabel,
+}
+
+/// Anchors queued readings one at a time and records their signatures. A reading whose
+/// submission fails stays stored without a proof, and is dead-lettered for a later retry.
+pub async fn run(mut jobs: mpsc::Receiver<ProofJob>, client: Arc<SolanaClient>, pool: PgPool) {
+    while let Some(job) = jobs.recv().await {
+        let sensor_id = job.reading.sensor_id;
+        let signature = match client.submit(&job.reading, &job.label).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                match e {
+                    SubmitError::InsufficientFunds => tracing::warn!(
+                        sensor_id,
+                        reading_id = job.reading_id,
+                        "Reading left unanchored, the Solana wallet is out of funds"
+                    ),
+                    SubmitError::Rpc(_) => tracing::error!(
+                        sensor_id,
+                        reading_id = job.reading_id,
+                        "Error submitting reading to Solana: {}",
+                        e
+                    ),
+                }
+                dead_letter(&pool, &job, &e).await;
+                continue;
+            }
+        };
+
+        if let Err(e) = db::set_reading_signature(&pool, job.reading_id, &signature).await {
+            tracing::error!(
+                sensor_id,
+                reading_id = job.reading_id,
+                "Error recording proof signature {}: {}",
+                signature,
+                e
+            );
+            continue;
+        }
+        if let Err(e) = db::clear_dead_letter(&pool, job.reading_id).await {
+            tracing::error!(
+                sensor_id,
+                reading_id = job.reading_id,
+                "Error clearing dead letter of anchored reading: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Persists a failed submission, so the reading isn't left pending unnoticed
+async fn dead_letter(pool: &PgPool, job: &ProofJob, error: &SubmitError) {
+    if let Err(e) = db::record_dead_letter(pool, job.reading_id, &error.to_string()).await {
+        tracing::error!(
+            sensor_id = job.reading.sensor_id,
+            reading_id = job.reading_id,
+            "Error dead-lettering reading: {}",
+            e
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use crate::config::RateLimitSettings;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sensors tracked before idle buckets are evicted, bounding memory under floods of bogus ids
+const MAX_TRACKED_SENSORS: usize = 10_000;
+
+/// Tokens left in a sensor's bucket as of `updated`
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-sensor token buckets throttling ingest: each holds up to `burst` readings and
+/// regains one every `interval`
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<i32, Bucket>>,
+    interval: Duration,
+    burst: f64,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            interval: settings.interval,
+            burst: settings.burst as f64,
+        }
+    }
+
+    /// Takes a token from the sensor's bucket, or returns how long until one is available.
+    /// A zero interval disables rate limiting.
+    pub fn check(&self, sensor_id: i32) -> Result<(), Duration> {
+        if self.interval.is_zero() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_SENSORS && !buckets.contains_key(&sensor_id) {
+            // Refilled buckets behave like untracked ones, so they can go
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(sensor_id).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(self.interval.mul_f64(1.0 - bucket.tokens))
+    }
+
+    /// Tokens in the bucket at `now`, capped at the burst size
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let regained = (now - bucket.updated).as_secs_f64() / self.interval.as_secs_f64();
+        (bucket.tokens + regained).min(self.burst)
+    }
+}
@@ -1,20 +1,126 @@
-use crate::crypto::reading_hash;
-use crate::db::SensorReading;
-use solana_client::rpc_client::RpcClient;
+use crate::config::ConfirmSettings;
+use crate::crypto::{reading_digest, reading_hash};
+use crate::db::{SensorLabel, SensorReading};
+use crate::{lamports, telemetry};
+use serde::Serialize;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::UiTransactionEncoding;
-use solana_client::rpc_response::OptionSerializer;
+use solana_client::rpc_response::{EncodedTransaction, OptionSerializer, UiTransactionStatusMeta};
 use solana_sdk::message::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{Keypair, Signature, Signer};
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// Memo program ID on mainnet/devnet
+const MEMO_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// Upper bound for a memo, leaving room in the 1232-byte transaction for signature and accounts
+const MAX_MEMO_LEN: usize = 512;
+
+/// Wait before the first retry of a confirmed submission, doubled on each further retry
+const CONFIRM_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Concurrent submissions tracked for deduplication, beyond which readings are submitted as-is
+const MAX_IN_FLIGHT_SUBMISSIONS: usize = 1024;
+
+/// Outcome of a submission, shared with concurrent submitters of the same reading
+type SharedSubmission = Arc<OnceCell<Result<String, SubmitError>>>;
+
+/// Why a reading could not be anchored on-chain
+#[derive(Debug, Clone)]
+pub enum SubmitError {
+    InsufficientFunds, // The fee payer ran dry, proofs are suspended until it is topped up
+    Rpc(String),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientFunds => write!(f, "Solana wallet has insufficient funds"),
+            Self::Rpc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ClientError> for SubmitError {
+    fn from(e: ClientError) -> Self {
+        Self::Rpc(e.to_string())
+    }
+}
+
+/// Memo layout used when anchoring readings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoVersion {
+    V1, // "pollution:v1:<hash>"
+    V2, // "pollution:v2:<hash>:{\"name\":...,\"location\":...}", self-describing for third parties
+}
+
+/// Memo anchoring a reading on-chain. A v2 memo that would exceed the size limit
+/// falls back to v1, so oversized sensor labels never block ingestion.
+pub fn memo(reading: &SensorReading, version: MemoVersion, sensor: &SensorLabel) -> String {
+    let hash = reading_hash(reading);
+    if version == MemoVersion::V2 {
+        let label = serde_json::json!({ "name": sensor.name, "location": sensor.location });
+        let memo = format!("pollution:v2:{}:{}", hash, label);
+        if memo.len() <= MAX_MEMO_LEN {
+            return memo;
+        }
+    }
+    format!("pollution:v1:{}", hash)
+}
+
+/// How readings are anchored on-chain
+#[derive(Debug, Clone, Copy)]
+pub enum ProofScheme {
+    Memo,                           // Memo program logging "pollution:v1:<hash>"
+    Program { program_id: Pubkey }, // Custom program receiving the raw hash as instruction data
+}
+
+/// Outcome of checking a reading against its on-chain proof
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    Matched,             // The transaction anchors this reading's hash
+    MemoMismatch,        // The transaction anchors a different payload
+    TransactionNotFound, // The signature is unknown to the cluster
+    NoMemoInTx,          // The transaction carries no proof instruction at all
+}
+
+impl Verification {
+    pub fn is_matched(self) -> bool {
+        self == Self::Matched
+    }
+}
 
 pub struct SolanaClient {
     pub rpc_client: RpcClient,
     pub keypair: Keypair,
+    pub proof_scheme: ProofScheme,
+    pub memo_version: MemoVersion,
+    pub min_balance: u64, // Lamports, a balance at or below it can't keep issuing proofs
+    in_flight: Mutex<HashMap<String, SharedSubmission>>, // Keyed by reading hash
+    depleted_since: Mutex<Option<Instant>>, // Set while the wallet can't pay for proofs
+    funds_recheck: Duration,
+    confirm: ConfirmSettings,
 }
 
 impl SolanaClient {
-    pub fn new(rpc_url: &str, keypair: &str) -> anyhow::Result<Self> {
+    pub fn new(
+        rpc_url: &str,
+        keypair: &str,
+        proof_scheme: ProofScheme,
+        memo_version: MemoVersion,
+        min_balance: u64,
+        funds_recheck: Duration,
+        confirm: ConfirmSettings,
+    ) -> anyhow::Result<Self> {
         // Read keypair from JSON
         let keypair_bytes: Vec<u8> = serde_json::from_str(keypair)?;
         let keypair = Keypair::try_from(keypair_bytes.as_slice())?;
@@ -23,82 +129,339 @@ impl SolanaClient {
         Ok(Self {
             rpc_client,
             keypair,
+            proof_scheme,
+            memo_version,
+            min_balance,
+            in_flight: Mutex::new(HashMap::new()),
+            depleted_since: Mutex::new(None),
+            funds_recheck,
+            confirm,
         })
     }
 
     /// Solana RPC connection sanity check
     pub async fn test_connection(&self) -> anyhow::Result<()> {
-        let version = self.rpc_client.get_version()?;
-        println!("Solana client version: {:?}", version);
+        let version = self.rpc_client.get_version().await?;
+        tracing::info!("Solana client version: {:?}", version);
         Ok(())
     }
 
+    /// Current balance of the linked wallet, in lamports
+    pub async fn balance(&self) -> anyhow::Result<u64> {
+        let balance = self.rpc_client.get_balance(&self.keypair.pubkey()).await?;
+        tracing::debug!("Balance: {} SOL", lamports::format_sol(balance));
+        Ok(balance)
+    }
+
     /// Checks the available balance of the linked wallet
     /// A minimum balance is required to issue transactions to Solana
-    pub fn enough_balance(&self) -> anyhow::Result<bool> {
-        let balance = self.rpc_client.get_balance(&self.keypair.pubkey())?;
-        //println!("Balance: {:?} SOL", balance as f64 / 1_000_000_000.0);
-        Ok(balance > 1_000_000) // 0.001 SOL minimum
+    pub async fn enough_balance(&self) -> anyhow::Result<bool> {
+        Ok(self.funded(self.balance().await?))
     }
 
-    pub async fn submit(&self, sensor_reading: &SensorReading) -> anyhow::Result<String> {
-        // Create memo with hash
-        let hash = reading_hash(sensor_reading);
-        let memo_data = format!("pollution:v1:{}", hash);
-
-        // Memo program ID on mainnet/devnet
-        let program_id = solana_sdk::pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+    /// Whether a balance, in lamports, is above the configured minimum
+    pub fn funded(&self, balance: u64) -> bool {
+        balance > self.min_balance
+    }
 
-        // Build memo instruction manually
-        let memo_ix = Instruction {
+    /// Builds the instruction anchoring a reading under the configured scheme
+    fn proof_instruction(
+        &self,
+        sensor_reading: &SensorReading,
+        sensor: &SensorLabel,
+    ) -> Instruction {
+        let (program_id, data) = match self.proof_scheme {
+            ProofScheme::Memo => (
+                MEMO_PROGRAM_ID,
+                memo(sensor_reading, self.memo_version, sensor).into_bytes(),
+            ),
+            ProofScheme::Program { program_id } => (
+                program_id,
+                reading_digest(sensor_reading).as_bytes().to_vec(),
+            ),
+        };
+        Instruction {
             program_id,
             accounts: vec![AccountMeta::new_readonly(self.keypair.pubkey(), true)],
-            data: memo_data.as_bytes().to_vec(),
+            data,
+        }
+    }
+
+    /// Anchors the reading on-chain. A concurrent submission of the same reading waits
+    /// for the one already in flight and reuses its signature instead of paying twice.
+    pub async fn submit(
+        &self,
+        sensor_reading: &SensorReading,
+        sensor: &SensorLabel,
+    ) -> Result<String, SubmitError> {
+        // Degraded mode: don't hit the RPC until the wallet may have been topped up
+        if self.depleted() {
+            return Err(SubmitError::InsufficientFunds);
+        }
+
+        let hash = reading_hash(sensor_reading);
+        let submission = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&hash) {
+                Some(submission) => Some(submission.clone()),
+                None if in_flight.len() < MAX_IN_FLIGHT_SUBMISSIONS => {
+                    let submission = SharedSubmission::default();
+                    in_flight.insert(hash.clone(), submission.clone());
+                    Some(submission)
+                }
+                None => None,
+            }
+        };
+        let Some(submission) = submission else {
+            return self.send_proof(sensor_reading, sensor).await;
+        };
+
+        let result = submission
+            .get_or_init(|| self.send_proof(sensor_reading, sensor))
+            .await
+            .clone();
+
+        // Completed submissions are forgotten, later retries go to the chain again
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(&hash)
+            .is_some_and(|current| Arc::ptr_eq(current, &submission))
+        {
+            in_flight.remove(&hash);
+        }
+        drop(in_flight);
+
+        result
+    }
+
+    async fn send_proof(
+        &self,
+        sensor_reading: &SensorReading,
+        sensor: &SensorLabel,
+    ) -> Result<String, SubmitError> {
+        // Build proof instruction manually
+        let proof_ix = self.proof_instruction(sensor_reading, sensor);
+
+        let started = Instant::now();
+        let result = match self.confirm.enabled {
+            true => self.send_and_confirm(proof_ix).await,
+            false => self.send_transaction(proof_ix).await,
+        };
+        let outcome = match &result {
+            Ok(_) => "success",
+            Err(SubmitError::InsufficientFunds) => "insufficient_funds",
+            Err(SubmitError::Rpc(_)) => "error",
         };
+        telemetry::record_submission(outcome, started.elapsed());
+        result
+    }
 
+    /// Signs the proof instruction into a transaction with a fresh blockhash
+    async fn sign(&self, proof_ix: Instruction) -> Result<Transaction, ClientError> {
         // Get recent blockhash
-        let recent_blockhash = self.rpc_client.get_latest_blockhash()?;
+        let recent_blockhash = self.rpc_client.get_latest_blockhash().await?;
 
-        let tx = Transaction::new_signed_with_payer(
-            &[memo_ix],
+        Ok(Transaction::new_signed_with_payer(
+            &[proof_ix],
             Some(&self.keypair.pubkey()),
             &[&self.keypair],
             recent_blockhash,
-        );
+        ))
+    }
 
-        // Option 1: Fire and forget (faster, but less reliable)
+    /// Fire and forget (faster, but a transaction dropped after being sent goes unnoticed)
+    async fn send_transaction(&self, proof_ix: Instruction) -> Result<String, SubmitError> {
+        let tx = self.sign(proof_ix).await?;
         let signature = tx.signatures[0].to_string();
-        self.rpc_client.send_transaction(&tx)?;
-
-        // Option 2: Wait for confirmation (catches errors)
-        //self.rpc_client.send_and_confirm_transaction(&tx)?;
+        if let Err(e) = self.rpc_client.send_transaction(&tx).await {
+            if Self::out_of_funds(&e) {
+                self.set_depleted(true);
+                return Err(SubmitError::InsufficientFunds);
+            }
+            return Err(e.into());
+        }
+        self.set_depleted(false);
 
         Ok(signature)
     }
 
-    pub async fn verify(&self, reading: SensorReading, signature: String) -> anyhow::Result<bool> {
-        // Calculate expected memo
-        let hash = reading_hash(&reading);
-        let expected_memo = format!("pollution:v1:{}", hash);
+    /// Waits for confirmation, retrying transient failures with a fresh blockhash and
+    /// exponential backoff. A transaction whose confirmation timed out may still land,
+    /// so a retry can anchor the same hash twice.
+    async fn send_and_confirm(&self, proof_ix: Instruction) -> Result<String, SubmitError> {
+        let mut backoff = CONFIRM_INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let result = match self.sign(proof_ix.clone()).await {
+                Ok(tx) => self.rpc_client.send_and_confirm_transaction(&tx).await,
+                Err(e) => Err(e),
+            };
+            let e = match result {
+                Ok(signature) => {
+                    self.set_depleted(false);
+                    return Ok(signature.to_string());
+                }
+                Err(e) => e,
+            };
+            if Self::out_of_funds(&e) {
+                self.set_depleted(true);
+                return Err(SubmitError::InsufficientFunds);
+            }
+            if attempt == self.confirm.retries || !Self::transient(&e) {
+                return Err(e.into());
+            }
+
+            attempt += 1;
+            tracing::warn!(
+                "Solana submission failed, retry {}/{} in {:?}: {}",
+                attempt,
+                self.confirm.retries,
+                backoff,
+                e
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Whether a failed submission may succeed when retried: expired blockhashes, network
+    /// errors and confirmation timeouts, as opposed to transactions the cluster rejected
+    fn transient(e: &ClientError) -> bool {
+        match e.get_transaction_error() {
+            Some(tx_error) => tx_error == TransactionError::BlockhashNotFound,
+            None => matches!(
+                e.kind(),
+                ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) | ClientErrorKind::RpcError(_)
+            ),
+        }
+    }
+
+    /// Whether the RPC refused the transaction because the fee payer can't cover it
+    /// (a wallet drained to zero lamports no longer exists, hence `AccountNotFound`)
+    fn out_of_funds(e: &ClientError) -> bool {
+        matches!(
+            e.get_transaction_error(),
+            Some(
+                TransactionError::InsufficientFundsForFee
+                    | TransactionError::InsufficientFundsForRent { .. }
+                    | TransactionError::AccountNotFound
+            )
+        )
+    }
+
+    /// Whether proofs are suspended, which lasts `funds_recheck` before the next attempt
+    pub fn depleted(&self) -> bool {
+        let depleted_since = self.depleted_since.lock().unwrap();
+        depleted_since.is_some_and(|since| since.elapsed() < self.funds_recheck)
+    }
+
+    /// Enters or leaves degraded mode, alerting operators on transitions
+    fn set_depleted(&self, depleted: bool) {
+        let mut depleted_since = self.depleted_since.lock().unwrap();
+        match (depleted_since.is_some(), depleted) {
+            (false, true) => tracing::error!(
+                "Solana wallet {} is out of funds, proofs are suspended until it is topped up",
+                self.keypair.pubkey()
+            ),
+            (true, false) => tracing::info!("Solana wallet funded again, proofs resumed"),
+            _ => {}
+        }
+        *depleted_since = depleted.then(Instant::now);
+    }
+
+    pub async fn verify(
+        &self,
+        reading: SensorReading,
+        signature: String,
+    ) -> anyhow::Result<Verification> {
+        self.verify_by_hash(&reading_hash(&reading), signature)
+            .await
+    }
+
+    /// Verifies a proof from the hex reading hash alone, for verifiers that only kept the
+    /// hash or whose stored fields no longer reproduce it (e.g. after schema changes)
+    pub async fn verify_by_hash(
+        &self,
+        hash: &str,
+        signature: String,
+    ) -> anyhow::Result<Verification> {
+        let digest = blake3::Hash::from_hex(hash)?;
 
         // Read transaction from blockchain
         let signature = Signature::from_str(&signature)?;
-        let tx = self
+        let tx = match self
             .rpc_client
-            .get_transaction(&signature, UiTransactionEncoding::Json)?;
+            .get_transaction(&signature, UiTransactionEncoding::Base64)
+            .await
+        {
+            Ok(tx) => tx,
+            // The RPC answers `null` for unknown signatures, which fails deserialization
+            Err(e) if matches!(e.kind(), ClientErrorKind::SerdeJson(_)) => {
+                return Ok(Verification::TransactionNotFound);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match self.proof_scheme {
+            ProofScheme::Memo => Ok(Self::memo_logged(tx.transaction.meta, &digest.to_hex())),
+            ProofScheme::Program { program_id } => Ok(Self::program_invoked(
+                &tx.transaction.transaction,
+                &program_id,
+                digest.as_bytes(),
+            )),
+        }
+    }
+
+    /// Whether the transaction invoked the program with the expected hash as instruction data
+    fn program_invoked(
+        tx: &EncodedTransaction,
+        program_id: &Pubkey,
+        expected_data: &[u8],
+    ) -> Verification {
+        let Some(decoded) = tx.decode() else {
+            return Verification::NoMemoInTx;
+        };
+        let keys = decoded.message.static_account_keys();
+        let mut invocations = decoded
+            .message
+            .instructions()
+            .iter()
+            .filter(|ix| keys.get(ix.program_id_index as usize) == Some(program_id))
+            .peekable();
 
+        if invocations.peek().is_none() {
+            Verification::NoMemoInTx
+        } else if invocations.any(|ix| ix.data == expected_data) {
+            Verification::Matched
+        } else {
+            Verification::MemoMismatch
+        }
+    }
+
+    /// Whether the memo program logged a memo of any version anchoring the expected hash
+    fn memo_logged(meta: Option<UiTransactionStatusMeta>, expected_hash: &str) -> Verification {
+        let expected_memos = [
+            format!("pollution:v1:{}", expected_hash),
+            format!("pollution:v2:{}:", expected_hash),
+        ];
         // Extract memo from transaction
-        if let Some(meta) = tx.transaction.meta {
-            if let OptionSerializer::Some(log_messages) = meta.log_messages {
-                for log in log_messages {
-                    // Memo program logs look like: "Program log: Memo (len 32): \"pollution:v1:...\""
-                    if log.contains(&expected_memo) {
-                        return Ok(true);
-                    }
+        let mut memo_found = false;
+        if let Some(meta) = meta
+            && let OptionSerializer::Some(log_messages) = meta.log_messages
+        {
+            for log in log_messages {
+                // Memo program logs look like: "Program log: Memo (len 32): \"pollution:v1:...\""
+                if expected_memos.iter().any(|memo| log.contains(memo)) {
+                    return Verification::Matched;
                 }
+                memo_found |= log.contains("Program log: Memo");
             }
         }
 
-        Ok(false)
+        if memo_found {
+            Verification::MemoMismatch
+        } else {
+            Verification::NoMemoInTx
+        }
     }
 }
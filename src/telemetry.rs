@@ -0,0 +1,53 @@
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::time::Duration;
+
+const READINGS_INGESTED: &str = "readings_ingested_total";
+const INGEST_FAILURES: &str = "ingest_failures_total";
+const SOLANA_SUBMISSIONS: &str = "solana_submissions_total";
+const SOLANA_SUBMISSION_SECONDS: &str = "solana_submission_duration_seconds";
+const LOGINS: &str = "logins_total";
+
+/// Histogram buckets for Solana submissions, from a fast RPC to one about to time out
+const SUBMISSION_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// How often histograms are compacted between scrapes
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Installs the process-wide Prometheus recorder, returning the handle rendering `/metrics`
+pub fn install() -> anyhow::Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(SOLANA_SUBMISSION_SECONDS.to_string()),
+            SUBMISSION_BUCKETS,
+        )?
+        .install_recorder()?;
+
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+    Ok(handle)
+}
+
+/// Counts readings stored and refused by an ingest transport (http, websocket, batch, stream)
+pub fn record_ingest(transport: &'static str, accepted: usize, failed: usize) {
+    counter!(READINGS_INGESTED, "transport" => transport).increment(accepted as u64);
+    counter!(INGEST_FAILURES, "transport" => transport).increment(failed as u64);
+}
+
+/// Records a proof sent to Solana, with its outcome (success, insufficient_funds, error)
+pub fn record_submission(outcome: &'static str, elapsed: Duration) {
+    counter!(SOLANA_SUBMISSIONS, "outcome" => outcome).increment(1);
+    histogram!(SOLANA_SUBMISSION_SECONDS).record(elapsed.as_secs_f64());
+}
+
+/// Records a login attempt, successful or refused for invalid credentials
+pub fn record_login(success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    counter!(LOGINS, "outcome" => outcome).increment(1);
+}